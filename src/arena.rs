@@ -0,0 +1,351 @@
+// sub-allocation of one big buffer into many small (offset, size) ranges
+// best-fit over free blocks, kept both by offset (for merging neighbours) and by size (for lookup)
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::{Buffer, MemoryLocation, Renderer};
+use ash::vk;
+
+// part of buffer. Also what descriptors can point to, see DescriptorInfo::buffer_ranges
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BufferRange {
+    pub buffer: vk::Buffer,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+}
+
+impl BufferRange {
+    pub fn new(buffer: &Buffer, offset: vk::DeviceSize, size: vk::DeviceSize) -> Self {
+        assert!(
+            offset + size <= buffer.size,
+            "Range is out of buffer bounds"
+        );
+        Self {
+            buffer: buffer.buffer,
+            offset,
+            size,
+        }
+    }
+
+    pub fn whole(buffer: &Buffer) -> Self {
+        Self::new(buffer, 0, buffer.size)
+    }
+}
+
+// how (badly) fragmented arena is
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ArenaReport {
+    pub capacity: vk::DeviceSize,
+    pub used: vk::DeviceSize,
+    pub allocations: usize,
+    pub free_blocks: usize,
+    pub largest_free_block: vk::DeviceSize,
+    // 0 when all free space is one block, close to 1 when it is scattered into tiny pieces
+    pub fragmentation: f32,
+}
+
+pub struct BufferArena {
+    pub buffer: Buffer,
+    pub min_alignment: vk::DeviceSize, // applied to every allocation
+    free_by_offset: BTreeMap<vk::DeviceSize, vk::DeviceSize>,
+    free_by_size: BTreeSet<(vk::DeviceSize, vk::DeviceSize)>, // (size, offset)
+    used: vk::DeviceSize,
+    allocations: usize,
+}
+
+impl BufferArena {
+    fn new(buffer: Buffer, min_alignment: vk::DeviceSize) -> Self {
+        let mut arena = Self {
+            min_alignment,
+            free_by_offset: BTreeMap::new(),
+            free_by_size: BTreeSet::new(),
+            used: 0,
+            allocations: 0,
+            buffer,
+        };
+        arena.insert_free(0, arena.buffer.size);
+        arena
+    }
+
+    pub fn alloc(&mut self, size: vk::DeviceSize) -> Option<BufferRange> {
+        self.alloc_aligned(size, 1)
+    }
+
+    // None if there is no free block big enough (even if total free space is)
+    pub fn alloc_aligned(
+        &mut self,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+    ) -> Option<BufferRange> {
+        assert!(size != 0);
+        let alignment = alignment.max(self.min_alignment).max(1);
+
+        // smallest block that fits once its start is aligned
+        let (block_size, block_offset, offset) = self
+            .free_by_size
+            .range((size, 0)..)
+            .map(|&(block_size, block_offset)| {
+                (
+                    block_size,
+                    block_offset,
+                    block_offset.next_multiple_of(alignment),
+                )
+            })
+            .find(|&(block_size, block_offset, offset)| {
+                offset + size <= block_offset + block_size
+            })?;
+        self.remove_free(block_offset, block_size);
+
+        let padding = offset - block_offset;
+        if padding != 0 {
+            self.insert_free(block_offset, padding);
+        }
+        let tail = block_size - padding - size;
+        if tail != 0 {
+            self.insert_free(offset + size, tail);
+        }
+
+        self.used += size;
+        self.allocations += 1;
+        Some(BufferRange {
+            buffer: self.buffer.buffer,
+            offset,
+            size,
+        })
+    }
+
+    // range has to come from alloc of this arena. Merges with free neighbours
+    pub fn free(&mut self, range: BufferRange) {
+        assert!(
+            range.buffer == self.buffer.buffer,
+            "Range is not from this arena"
+        );
+        let mut offset = range.offset;
+        let mut size = range.size;
+
+        if let Some((&prev_offset, &prev_size)) = self.free_by_offset.range(..offset).next_back() {
+            assert!(
+                prev_offset + prev_size <= offset,
+                "Double free of arena range"
+            );
+            if prev_offset + prev_size == offset {
+                self.remove_free(prev_offset, prev_size);
+                offset = prev_offset;
+                size += prev_size;
+            }
+        }
+        if let Some((&next_offset, &next_size)) = self.free_by_offset.range(range.offset..).next() {
+            let end = range.offset + range.size;
+            assert!(end <= next_offset, "Double free of arena range");
+            if end == next_offset {
+                self.remove_free(next_offset, next_size);
+                size += next_size;
+            }
+        }
+        self.insert_free(offset, size);
+
+        self.used -= range.size;
+        self.allocations -= 1;
+    }
+
+    // frees everything at once
+    pub fn reset(&mut self) {
+        self.free_by_offset.clear();
+        self.free_by_size.clear();
+        self.insert_free(0, self.buffer.size);
+        self.used = 0;
+        self.allocations = 0;
+    }
+
+    pub fn report(&self) -> ArenaReport {
+        let free = self.buffer.size - self.used;
+        let largest_free_block = self.free_by_size.last().map_or(0, |&(size, _)| size);
+        ArenaReport {
+            capacity: self.buffer.size,
+            used: self.used,
+            allocations: self.allocations,
+            free_blocks: self.free_by_offset.len(),
+            largest_free_block,
+            fragmentation: if free == 0 {
+                0.0
+            } else {
+                1.0 - largest_free_block as f32 / free as f32
+            },
+        }
+    }
+
+    // GPU pointer to allocated range (needs buffer device address enabled)
+    pub fn device_address(&self, range: &BufferRange) -> vk::DeviceAddress {
+        assert!(
+            range.buffer == self.buffer.buffer,
+            "Range is not from this arena"
+        );
+        self.buffer.device_address() + range.offset
+    }
+
+    fn insert_free(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        self.free_by_offset.insert(offset, size);
+        self.free_by_size.insert((size, offset));
+    }
+
+    fn remove_free(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        self.free_by_offset.remove(&offset);
+        self.free_by_size.remove(&(size, offset));
+    }
+}
+
+impl Renderer {
+    // min_alignment is picked from usage, so ranges can be bound as uniform / storage buffers
+    #[cold]
+    #[optimize(size)]
+    pub fn create_buffer_arena(
+        &mut self,
+        usage: vk::BufferUsageFlags,
+        capacity: usize,
+        location: MemoryLocation,
+    ) -> BufferArena {
+        let limits = unsafe {
+            self.instance.get_physical_device_properties(self.vulkan_data.physical_device)
+        }
+        .limits;
+
+        // 16 is enough for any vertex / index data
+        let mut min_alignment = 16;
+        if usage.contains(vk::BufferUsageFlags::UNIFORM_BUFFER) {
+            min_alignment = min_alignment.max(limits.min_uniform_buffer_offset_alignment);
+        }
+        if usage.contains(vk::BufferUsageFlags::STORAGE_BUFFER) {
+            min_alignment = min_alignment.max(limits.min_storage_buffer_offset_alignment);
+        }
+
+        let buffer = self.create_buffer(usage, capacity, location);
+        BufferArena::new(buffer, min_alignment)
+    }
+
+    #[cold]
+    #[optimize(size)]
+    pub fn destroy_buffer_arena(&mut self, arena: BufferArena) {
+        self.destroy_buffer(arena.buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arena(capacity: vk::DeviceSize, min_alignment: vk::DeviceSize) -> BufferArena {
+        let buffer = Buffer {
+            size: capacity,
+            ..Default::default()
+        };
+        BufferArena::new(buffer, min_alignment)
+    }
+
+    #[test]
+    fn alloc_until_full() {
+        let mut arena = arena(256, 1);
+        let a = arena.alloc(100).unwrap();
+        let b = arena.alloc(156).unwrap();
+        assert_eq!((a.offset, a.size), (0, 100));
+        assert_eq!((b.offset, b.size), (100, 156));
+        assert!(arena.alloc(1).is_none());
+
+        let report = arena.report();
+        assert_eq!(report.used, 256);
+        assert_eq!(report.allocations, 2);
+        assert_eq!(report.free_blocks, 0);
+    }
+
+    #[test]
+    fn exact_fit_aligned_block() {
+        let mut arena = arena(256, 64);
+        let a = arena.alloc(64).unwrap();
+        let _b = arena.alloc(192).unwrap();
+        arena.free(a);
+        // free block is exactly 64 at aligned offset 0, has to be reused
+        let c = arena.alloc(64).unwrap();
+        assert_eq!(c.offset, 0);
+    }
+
+    #[test]
+    fn alignment_pads_and_keeps_padding_free() {
+        let mut arena = arena(256, 1);
+        let a = arena.alloc(10).unwrap();
+        let b = arena.alloc_aligned(32, 64).unwrap();
+        assert_eq!(b.offset, 64);
+        // padding 10..64 stays usable
+        let c = arena.alloc(54).unwrap();
+        assert_eq!(c.offset, 10);
+
+        arena.free(a);
+        arena.free(b);
+        arena.free(c);
+        assert_eq!(arena.report().free_blocks, 1);
+    }
+
+    #[test]
+    fn unaligned_block_too_small_after_padding() {
+        let mut arena = arena(256, 1);
+        let _a = arena.alloc(4).unwrap();
+        let hole = arena.alloc(40).unwrap(); // 4..44
+        let _c = arena.alloc(212).unwrap();
+        arena.free(hole);
+        // block is 40 bytes, but aligned start 16 leaves only 28
+        assert!(arena.alloc_aligned(40, 16).is_none());
+        let d = arena.alloc_aligned(28, 16).unwrap();
+        assert_eq!(d.offset, 16);
+        assert_eq!(arena.alloc(12).unwrap().offset, 4);
+    }
+
+    #[test]
+    fn free_coalesces_neighbours() {
+        let mut arena = arena(300, 1);
+        let a = arena.alloc(100).unwrap();
+        let b = arena.alloc(100).unwrap();
+        let c = arena.alloc(100).unwrap();
+
+        arena.free(a);
+        arena.free(c);
+        assert_eq!(arena.report().free_blocks, 2);
+        assert_eq!(arena.report().largest_free_block, 100);
+
+        // merges with both sides
+        arena.free(b);
+        let report = arena.report();
+        assert_eq!(report.free_blocks, 1);
+        assert_eq!(report.largest_free_block, 300);
+        assert_eq!(report.used, 0);
+        assert_eq!(report.fragmentation, 0.0);
+    }
+
+    #[test]
+    fn best_fit_picks_smallest_block() {
+        let mut arena = arena(400, 1);
+        let a = arena.alloc(200).unwrap();
+        let _b = arena.alloc(10).unwrap();
+        let c = arena.alloc(50).unwrap();
+        let _d = arena.alloc(140).unwrap();
+        arena.free(a);
+        arena.free(c);
+        assert_eq!(arena.alloc(40).unwrap().offset, c.offset);
+    }
+
+    #[test]
+    #[should_panic(expected = "Double free")]
+    fn double_free_panics() {
+        let mut arena = arena(256, 1);
+        let a = arena.alloc(16).unwrap();
+        let _b = arena.alloc(16).unwrap();
+        arena.free(a);
+        arena.free(a);
+    }
+
+    #[test]
+    fn reset_frees_everything() {
+        let mut arena = arena(128, 1);
+        arena.alloc(64).unwrap();
+        arena.alloc(64).unwrap();
+        arena.reset();
+        assert_eq!(arena.alloc(128).unwrap().offset, 0);
+    }
+}
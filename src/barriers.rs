@@ -6,7 +6,572 @@ use ash::vk::{self, BufferMemoryBarrier, MemoryBarrier, WHOLE_SIZE};
 // just a wrapper for barriers that suits my needs
 // it is missing a lot but it does not matter anyways - drivers dont give a fuck about precise barriers
 
+// access bits that make previous contents "dirty" and thus require a barrier before anything else touches them
+const WRITE_ACCESS: vk::AccessFlags2 = vk::AccessFlags2::from_raw(
+    vk::AccessFlags2::SHADER_WRITE.as_raw()
+        | vk::AccessFlags2::SHADER_STORAGE_WRITE.as_raw()
+        | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags2::TRANSFER_WRITE.as_raw()
+        | vk::AccessFlags2::HOST_WRITE.as_raw()
+        | vk::AccessFlags2::MEMORY_WRITE.as_raw(),
+);
+
+// what was last done to a single subresource (mip + layer) of an Image
+// used to figure out src side of barriers automatically
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImageState {
+    pub layout: vk::ImageLayout,
+    pub access: vk::AccessFlags2,
+    pub stage: vk::PipelineStageFlags2,
+}
+
+// what image is going to be used for next. Maps to (layout, access, stage) triple
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageUsage {
+    General,         // whatever, GENERAL layout and full barrier. Lumal's default
+    ShaderRead,      // sampled / read in any shader
+    ShaderWrite,     // storage image, read and written in any shader
+    ColorAttachment, // render target
+    DepthAttachment, // depth(-stencil) render target
+    DepthRead,       // read-only depth(-stencil), both as attachment and in shaders
+    TransferSrc,     // copy / blit source
+    TransferDst,     // copy / blit destination
+    Present,         // swapchain image handed to presentation engine
+}
+
+const SHADER_STAGES: vk::PipelineStageFlags2 = vk::PipelineStageFlags2::from_raw(
+    vk::PipelineStageFlags2::VERTEX_SHADER.as_raw()
+        | vk::PipelineStageFlags2::FRAGMENT_SHADER.as_raw()
+        | vk::PipelineStageFlags2::COMPUTE_SHADER.as_raw(),
+);
+
+impl ImageUsage {
+    pub fn state(self) -> ImageState {
+        let (layout, access, stage) = match self {
+            ImageUsage::General => (
+                vk::ImageLayout::GENERAL,
+                vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE,
+                vk::PipelineStageFlags2::ALL_COMMANDS,
+            ),
+            ImageUsage::ShaderRead => (
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::AccessFlags2::SHADER_READ,
+                SHADER_STAGES,
+            ),
+            ImageUsage::ShaderWrite => (
+                vk::ImageLayout::GENERAL,
+                vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE,
+                SHADER_STAGES,
+            ),
+            ImageUsage::ColorAttachment => (
+                vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                vk::AccessFlags2::COLOR_ATTACHMENT_READ | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+                vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+            ),
+            ImageUsage::DepthAttachment => (
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+                vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+            ),
+            ImageUsage::DepthRead => (
+                vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+                vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags2::SHADER_READ,
+                vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS
+                    | SHADER_STAGES,
+            ),
+            ImageUsage::TransferSrc => (
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::AccessFlags2::TRANSFER_READ,
+                vk::PipelineStageFlags2::TRANSFER,
+            ),
+            ImageUsage::TransferDst => (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::AccessFlags2::TRANSFER_WRITE,
+                vk::PipelineStageFlags2::TRANSFER,
+            ),
+            ImageUsage::Present => (
+                vk::ImageLayout::PRESENT_SRC_KHR,
+                vk::AccessFlags2::NONE,
+                vk::PipelineStageFlags2::NONE,
+            ),
+        };
+        ImageState {
+            layout,
+            access,
+            stage,
+        }
+    }
+}
+
+impl ImageState {
+    // reads after reads in the same layout do not need any barrier
+    pub fn needs_barrier_to(&self, next: &ImageState) -> bool {
+        self.layout != next.layout
+            || self.access.intersects(WRITE_ACCESS)
+            || next.access.intersects(WRITE_ACCESS)
+    }
+}
+
+impl Image {
+    pub fn state_index(&self, mip: u32, layer: u32) -> usize {
+        assert!(mip < self.mip_levels && layer < self.layers);
+        (layer * self.mip_levels + mip) as usize
+    }
+
+    pub fn state(&self, mip: u32, layer: u32) -> ImageState {
+        self.states[self.state_index(mip, layer)]
+    }
+
+    // tells tracker that something outside of it (render pass final_layout, presentation, etc.)
+    // changed the image. Applies to every subresource
+    pub fn assume_state(&mut self, state: ImageState) {
+        self.states.iter_mut().for_each(|s| *s = state);
+    }
+
+    pub fn assume_usage(&mut self, usage: ImageUsage) {
+        self.assume_state(usage.state());
+    }
+
+    // every mip of every layer
+    pub fn whole_range(&self) -> vk::ImageSubresourceRange {
+        self.subresource_range(0, self.mip_levels, 0, self.layers)
+    }
+
+    // single mip of every layer
+    pub fn mip_range(&self, mip: u32) -> vk::ImageSubresourceRange {
+        self.subresource_range(mip, 1, 0, self.layers)
+    }
+
+    pub fn subresource_range(
+        &self,
+        base_mip_level: u32,
+        level_count: u32,
+        base_array_layer: u32,
+        layer_count: u32,
+    ) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange {
+            aspect_mask: self.aspect,
+            base_mip_level,
+            level_count,
+            base_array_layer,
+            layer_count,
+        }
+    }
+
+    // turns REMAINING_* into actual counts
+    pub fn resolve_range(&self, range: vk::ImageSubresourceRange) -> vk::ImageSubresourceRange {
+        let mut resolved = range;
+        if range.level_count == vk::REMAINING_MIP_LEVELS {
+            resolved.level_count = self.mip_levels - range.base_mip_level;
+        }
+        if range.layer_count == vk::REMAINING_ARRAY_LAYERS {
+            resolved.layer_count = self.layers - range.base_array_layer;
+        }
+        assert!(resolved.base_mip_level + resolved.level_count <= self.mip_levels);
+        assert!(resolved.base_array_layer + resolved.layer_count <= self.layers);
+        resolved
+    }
+}
+
+// collects any number of barriers to then issue them in a single vkCmdPipelineBarrier(2)
+// stages and accesses are always synchronization2 ones, they are lowered if device does not support it
+#[derive(Default, Debug)]
+pub struct BarrierBatch {
+    pub memory: Vec<vk::MemoryBarrier2<'static>>,
+    pub buffers: Vec<vk::BufferMemoryBarrier2<'static>>,
+    pub images: Vec<vk::ImageMemoryBarrier2<'static>>,
+}
+
+impl BarrierBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_empty() && self.buffers.is_empty() && self.images.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.memory.clear();
+        self.buffers.clear();
+        self.images.clear();
+    }
+
+    pub fn global(
+        &mut self,
+        src_stage_mask: vk::PipelineStageFlags2,
+        src_access_mask: vk::AccessFlags2,
+        dst_stage_mask: vk::PipelineStageFlags2,
+        dst_access_mask: vk::AccessFlags2,
+    ) -> &mut Self {
+        self.memory.push(vk::MemoryBarrier2 {
+            src_stage_mask,
+            src_access_mask,
+            dst_stage_mask,
+            dst_access_mask,
+            ..Default::default()
+        });
+        self
+    }
+
+    // whole buffer
+    pub fn buffer(
+        &mut self,
+        buffer: &Buffer,
+        src_stage_mask: vk::PipelineStageFlags2,
+        src_access_mask: vk::AccessFlags2,
+        dst_stage_mask: vk::PipelineStageFlags2,
+        dst_access_mask: vk::AccessFlags2,
+    ) -> &mut Self {
+        self.buffers.push(vk::BufferMemoryBarrier2 {
+            src_stage_mask,
+            src_access_mask,
+            dst_stage_mask,
+            dst_access_mask,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            buffer: buffer.buffer,
+            offset: 0,
+            size: WHOLE_SIZE,
+            ..Default::default()
+        });
+        self
+    }
+
+    // whole image, untracked. Does not touch image states
+    pub fn image(&mut self, image: &Image, from: ImageState, to: ImageState) -> &mut Self {
+        self.image_range(image, image.whole_range(), from, to)
+    }
+
+    // part of image, untracked. Does not touch image states
+    pub fn image_range(
+        &mut self,
+        image: &Image,
+        range: vk::ImageSubresourceRange,
+        from: ImageState,
+        to: ImageState,
+    ) -> &mut Self {
+        self.images.push(vk::ImageMemoryBarrier2 {
+            src_stage_mask: from.stage,
+            src_access_mask: from.access & WRITE_ACCESS,
+            dst_stage_mask: to.stage,
+            dst_access_mask: to.access,
+            old_layout: from.layout,
+            new_layout: to.layout,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            image: image.image,
+            subresource_range: range,
+            ..Default::default()
+        });
+        self
+    }
+
+    pub fn push_memory(&mut self, barrier: vk::MemoryBarrier2<'static>) -> &mut Self {
+        self.memory.push(barrier);
+        self
+    }
+
+    pub fn push_buffer(&mut self, barrier: vk::BufferMemoryBarrier2<'static>) -> &mut Self {
+        self.buffers.push(barrier);
+        self
+    }
+
+    pub fn push_image(&mut self, barrier: vk::ImageMemoryBarrier2<'static>) -> &mut Self {
+        self.images.push(barrier);
+        self
+    }
+
+    // tracked transition. Adds barriers only for subresources that need them and updates image states
+    pub fn transition(&mut self, image: &mut Image, usage: ImageUsage) -> &mut Self {
+        self.transition_state(image, usage.state())
+    }
+
+    pub fn transition_state(&mut self, image: &mut Image, new: ImageState) -> &mut Self {
+        self.transition_range_state(image, image.whole_range(), new)
+    }
+
+    pub fn transition_range(
+        &mut self,
+        image: &mut Image,
+        range: vk::ImageSubresourceRange,
+        usage: ImageUsage,
+    ) -> &mut Self {
+        self.transition_range_state(image, range, usage.state())
+    }
+
+    pub fn transition_range_state(
+        &mut self,
+        image: &mut Image,
+        range: vk::ImageSubresourceRange,
+        new: ImageState,
+    ) -> &mut Self {
+        let range = image.resolve_range(range);
+        let mip_end = range.base_mip_level + range.level_count;
+        let layer_end = range.base_array_layer + range.layer_count;
+        for layer in range.base_array_layer..layer_end {
+            // consecutive mips with the same old state are merged into one barrier
+            let mut run: Option<(u32, ImageState)> = None;
+            for mip in range.base_mip_level..=mip_end {
+                let old = if mip < mip_end {
+                    let index = image.state_index(mip, layer);
+                    let old = image.states[index];
+                    if old.needs_barrier_to(&new) {
+                        image.states[index] = new;
+                        Some(old)
+                    } else {
+                        // read after read, just remember one more reader
+                        image.states[index].access |= new.access;
+                        image.states[index].stage |= new.stage;
+                        None
+                    }
+                } else {
+                    None // flushes last run
+                };
+
+                if let Some((base_mip, run_old)) = run {
+                    if old == Some(run_old) {
+                        continue;
+                    }
+                    self.images.push(vk::ImageMemoryBarrier2 {
+                        src_stage_mask: run_old.stage,
+                        // only writes have to be made available, reads just need execution dependency
+                        src_access_mask: run_old.access & WRITE_ACCESS,
+                        dst_stage_mask: new.stage,
+                        dst_access_mask: new.access,
+                        old_layout: run_old.layout,
+                        new_layout: new.layout,
+                        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        image: image.image,
+                        subresource_range: vk::ImageSubresourceRange {
+                            aspect_mask: range.aspect_mask,
+                            base_mip_level: base_mip,
+                            level_count: mip - base_mip,
+                            base_array_layer: layer,
+                            layer_count: 1,
+                        },
+                        ..Default::default()
+                    });
+                }
+                run = old.map(|old| (mip, old));
+            }
+        }
+        self
+    }
+}
+
+// synchronization2 stages / accesses that have no direct legacy bit are widened to ones that cover them
+fn legacy_stages(stages: vk::PipelineStageFlags2) -> vk::PipelineStageFlags {
+    use vk::PipelineStageFlags as S1;
+    use vk::PipelineStageFlags2 as S2;
+    // lower 32 bits are the same in both
+    let mut legacy = S1::from_raw(stages.as_raw() as u32);
+    if stages.intersects(S2::COPY | S2::RESOLVE | S2::BLIT | S2::CLEAR) {
+        legacy |= S1::TRANSFER;
+    }
+    if stages.intersects(S2::INDEX_INPUT | S2::VERTEX_ATTRIBUTE_INPUT) {
+        legacy |= S1::VERTEX_INPUT;
+    }
+    if stages.intersects(S2::PRE_RASTERIZATION_SHADERS) {
+        legacy |= S1::VERTEX_SHADER
+            | S1::TESSELLATION_CONTROL_SHADER
+            | S1::TESSELLATION_EVALUATION_SHADER
+            | S1::GEOMETRY_SHADER;
+    }
+    legacy
+}
+
+fn legacy_access(access: vk::AccessFlags2) -> vk::AccessFlags {
+    use vk::AccessFlags as A1;
+    use vk::AccessFlags2 as A2;
+    let mut legacy = A1::from_raw(access.as_raw() as u32);
+    if access.intersects(A2::SHADER_SAMPLED_READ | A2::SHADER_STORAGE_READ) {
+        legacy |= A1::SHADER_READ;
+    }
+    if access.intersects(A2::SHADER_STORAGE_WRITE) {
+        legacy |= A1::SHADER_WRITE;
+    }
+    legacy
+}
+
 impl Renderer {
+    // issues all barriers in batch with one call and clears it
+    #[cold]
+    #[optimize(speed)]
+    pub fn cmd_flush_barriers(&self, cmdbuf: &vk::CommandBuffer, batch: &mut BarrierBatch) {
+        if batch.is_empty() {
+            return;
+        }
+
+        if self.vulkan_data.synchronization2 {
+            let dependency_info = vk::DependencyInfo {
+                memory_barrier_count: batch.memory.len() as u32,
+                p_memory_barriers: batch.memory.as_ptr(),
+                buffer_memory_barrier_count: batch.buffers.len() as u32,
+                p_buffer_memory_barriers: batch.buffers.as_ptr(),
+                image_memory_barrier_count: batch.images.len() as u32,
+                p_image_memory_barriers: batch.images.as_ptr(),
+                ..Default::default()
+            };
+            unsafe {
+                match &self.synchronization2_loader {
+                    Some(loader) => loader.cmd_pipeline_barrier2(*cmdbuf, &dependency_info),
+                    None => self.device.cmd_pipeline_barrier2(*cmdbuf, &dependency_info),
+                }
+            };
+        } else {
+            // legacy barriers have one pair of stage masks for everything
+            let mut src_stage_mask = vk::PipelineStageFlags2::NONE;
+            let mut dst_stage_mask = vk::PipelineStageFlags2::NONE;
+
+            let memory: Vec<vk::MemoryBarrier> = batch
+                .memory
+                .iter()
+                .map(|b| {
+                    src_stage_mask |= b.src_stage_mask;
+                    dst_stage_mask |= b.dst_stage_mask;
+                    vk::MemoryBarrier {
+                        src_access_mask: legacy_access(b.src_access_mask),
+                        dst_access_mask: legacy_access(b.dst_access_mask),
+                        ..Default::default()
+                    }
+                })
+                .collect();
+            let buffers: Vec<vk::BufferMemoryBarrier> = batch
+                .buffers
+                .iter()
+                .map(|b| {
+                    src_stage_mask |= b.src_stage_mask;
+                    dst_stage_mask |= b.dst_stage_mask;
+                    vk::BufferMemoryBarrier {
+                        src_access_mask: legacy_access(b.src_access_mask),
+                        dst_access_mask: legacy_access(b.dst_access_mask),
+                        src_queue_family_index: b.src_queue_family_index,
+                        dst_queue_family_index: b.dst_queue_family_index,
+                        buffer: b.buffer,
+                        offset: b.offset,
+                        size: b.size,
+                        ..Default::default()
+                    }
+                })
+                .collect();
+            let images: Vec<vk::ImageMemoryBarrier> = batch
+                .images
+                .iter()
+                .map(|b| {
+                    src_stage_mask |= b.src_stage_mask;
+                    dst_stage_mask |= b.dst_stage_mask;
+                    vk::ImageMemoryBarrier {
+                        src_access_mask: legacy_access(b.src_access_mask),
+                        dst_access_mask: legacy_access(b.dst_access_mask),
+                        old_layout: b.old_layout,
+                        new_layout: b.new_layout,
+                        src_queue_family_index: b.src_queue_family_index,
+                        dst_queue_family_index: b.dst_queue_family_index,
+                        image: b.image,
+                        subresource_range: b.subresource_range,
+                        ..Default::default()
+                    }
+                })
+                .collect();
+
+            let mut src_stage_mask = legacy_stages(src_stage_mask);
+            let mut dst_stage_mask = legacy_stages(dst_stage_mask);
+            // NONE is not a thing without synchronization2
+            if src_stage_mask.is_empty() {
+                src_stage_mask = vk::PipelineStageFlags::TOP_OF_PIPE;
+            }
+            if dst_stage_mask.is_empty() {
+                dst_stage_mask = vk::PipelineStageFlags::BOTTOM_OF_PIPE;
+            }
+
+            unsafe {
+                self.device.cmd_pipeline_barrier(
+                    *cmdbuf,
+                    src_stage_mask,
+                    dst_stage_mask,
+                    vk::DependencyFlags::empty(),
+                    &memory,
+                    &buffers,
+                    &images,
+                )
+            };
+        }
+
+        batch.clear();
+    }
+
+    // transitions whole image into state needed for usage, emitting barrier only when needed
+    #[cold]
+    #[optimize(speed)]
+    pub fn transition(&self, cmdbuf: &vk::CommandBuffer, image: &mut Image, usage: ImageUsage) {
+        self.transition_state(cmdbuf, image, usage.state());
+    }
+
+    #[cold]
+    #[optimize(speed)]
+    pub fn transition_state(&self, cmdbuf: &vk::CommandBuffer, image: &mut Image, new: ImageState) {
+        let mut batch = BarrierBatch::new();
+        batch.transition_state(image, new);
+        self.cmd_flush_barriers(cmdbuf, &mut batch);
+    }
+
+    // same as transition, but only for given mips and layers
+    #[cold]
+    #[optimize(speed)]
+    pub fn transition_range(
+        &self,
+        cmdbuf: &vk::CommandBuffer,
+        image: &mut Image,
+        range: vk::ImageSubresourceRange,
+        usage: ImageUsage,
+    ) {
+        let mut batch = BarrierBatch::new();
+        batch.transition_range(image, range, usage);
+        self.cmd_flush_barriers(cmdbuf, &mut batch);
+    }
+
+    // transitions single mip (of every layer) and returns view of it
+    // useful for per-mip passes like downsampling
+    #[cold]
+    #[optimize(speed)]
+    pub fn transition_mip(
+        &self,
+        cmdbuf: &vk::CommandBuffer,
+        image: &mut Image,
+        mip: u32,
+        usage: ImageUsage,
+    ) -> vk::ImageView {
+        self.transition_range(cmdbuf, image, image.mip_range(mip), usage);
+        if image.mip_views.is_empty() {
+            assert!(mip == 0, "Image has no mip views");
+            image.view
+        } else {
+            image.mip_views[mip as usize]
+        }
+    }
+
+    // untracked barrier on part of image
+    #[cold]
+    #[optimize(speed)]
+    pub fn image_memory_barrier_range(
+        &self,
+        cmdbuf: &vk::CommandBuffer,
+        image: &Image,
+        range: vk::ImageSubresourceRange,
+        from: ImageState,
+        to: ImageState,
+    ) {
+        let mut batch = BarrierBatch::new();
+        batch.image_range(image, range, from, to);
+        self.cmd_flush_barriers(cmdbuf, &mut batch);
+    }
+
     #[cold]
     #[optimize(speed)]
     pub fn image_memory_barrier(
@@ -26,13 +591,7 @@ impl Renderer {
             src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
             dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
             image: image.image,
-            subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: image.aspect,
-                base_mip_level: 0,
-                level_count: 1,
-                base_array_layer: 0,
-                layer_count: 1,
-            },
+            subresource_range: image.whole_range(),
             src_access_mask,
             dst_access_mask,
             ..Default::default()
@@ -0,0 +1,295 @@
+// global descriptor table for bindless rendering (VK_EXT_descriptor_indexing, core in 1.2)
+// one set with big partially bound arrays, resources get stable index on add and keep it until removed
+// requires LumalSettings::bindless
+//
+// binding 0 - sampler2D textures[]   (COMBINED_IMAGE_SAMPLER, SHADER_READ_ONLY_OPTIMAL)
+// binding 1 - image2D images[]       (STORAGE_IMAGE, GENERAL)
+// binding 2 - buffer buffers[]       (STORAGE_BUFFER, variable count)
+// index them with nonuniformEXT() in shaders if index is not dynamically uniform
+
+use crate::{arena::BufferRange, Image, Renderer};
+use ash::vk;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BindlessKind {
+    Texture = 0,
+    StorageImage = 1,
+    StorageBuffer = 2,
+}
+
+impl BindlessKind {
+    pub fn binding(self) -> u32 {
+        self as u32
+    }
+
+    pub fn descriptor_type(self) -> vk::DescriptorType {
+        match self {
+            BindlessKind::Texture => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            BindlessKind::StorageImage => vk::DescriptorType::STORAGE_IMAGE,
+            BindlessKind::StorageBuffer => vk::DescriptorType::STORAGE_BUFFER,
+        }
+    }
+}
+
+const BINDLESS_KINDS: [BindlessKind; 3] = [
+    BindlessKind::Texture,
+    BindlessKind::StorageImage,
+    BindlessKind::StorageBuffer,
+];
+
+// stable indices for one binding
+#[derive(Default)]
+struct SlotAllocator {
+    next: u32,                // never used slots start here
+    free: Vec<u32>,           // ready for reuse
+    pending: Vec<(u32, i32)>, // (index, frame it was removed at), GPU might still read them
+}
+
+impl SlotAllocator {
+    // completed_frame is Renderer::completed_frame - newest frame whose fence was waited on
+    fn alloc(&mut self, capacity: u32, completed_frame: i32) -> u32 {
+        // slots removed in frames GPU has finished are not referenced by anything anymore
+        self.pending.retain(|&(index, removed_at)| {
+            let ready = removed_at <= completed_frame;
+            if ready {
+                self.free.push(index);
+            }
+            !ready
+        });
+
+        if let Some(index) = self.free.pop() {
+            return index;
+        }
+        assert!(self.next < capacity, "Bindless table is full");
+        self.next += 1;
+        self.next - 1
+    }
+
+    fn remove(&mut self, index: u32, frame: i32) {
+        assert!(index < self.next, "Bindless index was never allocated");
+        assert!(
+            !self.free.contains(&index) && !self.pending.iter().any(|&(i, _)| i == index),
+            "Bindless index removed twice"
+        );
+        self.pending.push((index, frame));
+    }
+}
+
+pub struct BindlessTable {
+    pub layout: vk::DescriptorSetLayout,
+    pub pool: vk::DescriptorPool,
+    pub set: vk::DescriptorSet, // single set, updated after bind - no per-frame copies
+    pub capacity: u32,          // per binding
+    slots: [SlotAllocator; 3],
+}
+
+impl BindlessTable {
+    // number of currently used slots of kind
+    pub fn len(&self, kind: BindlessKind) -> u32 {
+        let slots = &self.slots[kind as usize];
+        slots.next - slots.free.len() as u32 - slots.pending.len() as u32
+    }
+
+    pub fn is_empty(&self, kind: BindlessKind) -> bool {
+        self.len(kind) == 0
+    }
+}
+
+impl Renderer {
+    #[cold]
+    #[optimize(size)]
+    pub fn create_bindless_table(&mut self, capacity: u32) -> BindlessTable {
+        assert!(
+            self.vulkan_data.bindless,
+            "Bindless table requires LumalSettings::bindless"
+        );
+        assert!(capacity > 0);
+
+        let stages = vk::ShaderStageFlags::ALL;
+        let bindings: Vec<vk::DescriptorSetLayoutBinding> = BINDLESS_KINDS
+            .iter()
+            .map(|kind| vk::DescriptorSetLayoutBinding {
+                binding: kind.binding(),
+                descriptor_type: kind.descriptor_type(),
+                descriptor_count: capacity,
+                stage_flags: stages,
+                ..Default::default()
+            })
+            .collect();
+
+        let common_flags = vk::DescriptorBindingFlags::PARTIALLY_BOUND
+            | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
+            | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING;
+        let binding_flags = [
+            common_flags,
+            common_flags,
+            // only last binding can be variable sized
+            common_flags | vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT,
+        ];
+        let binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo {
+            binding_count: binding_flags.len() as u32,
+            p_binding_flags: binding_flags.as_ptr(),
+            ..Default::default()
+        };
+
+        let layout = unsafe {
+            self.device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo {
+                    p_next: &binding_flags_info as *const _ as *const std::ffi::c_void,
+                    flags: vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL,
+                    binding_count: bindings.len() as u32,
+                    p_bindings: bindings.as_ptr(),
+                    ..Default::default()
+                },
+                None,
+            )
+        }
+        .unwrap();
+
+        let pool_sizes: Vec<vk::DescriptorPoolSize> = BINDLESS_KINDS
+            .iter()
+            .map(|kind| vk::DescriptorPoolSize {
+                ty: kind.descriptor_type(),
+                descriptor_count: capacity,
+            })
+            .collect();
+        let pool = unsafe {
+            self.device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo {
+                    flags: vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND,
+                    max_sets: 1,
+                    pool_size_count: pool_sizes.len() as u32,
+                    p_pool_sizes: pool_sizes.as_ptr(),
+                    ..Default::default()
+                },
+                None,
+            )
+        }
+        .unwrap();
+
+        let variable_counts = [capacity];
+        let variable_count_info = vk::DescriptorSetVariableDescriptorCountAllocateInfo {
+            descriptor_set_count: 1,
+            p_descriptor_counts: variable_counts.as_ptr(),
+            ..Default::default()
+        };
+        let set = unsafe {
+            self.device.allocate_descriptor_sets(&vk::DescriptorSetAllocateInfo {
+                p_next: &variable_count_info as *const _ as *const std::ffi::c_void,
+                descriptor_pool: pool,
+                descriptor_set_count: 1,
+                p_set_layouts: &layout,
+                ..Default::default()
+            })
+        }
+        .unwrap()[0];
+
+        BindlessTable {
+            layout,
+            pool,
+            set,
+            capacity,
+            slots: Default::default(),
+        }
+    }
+
+    #[cold]
+    #[optimize(size)]
+    pub fn destroy_bindless_table(&mut self, table: BindlessTable) {
+        unsafe {
+            // set is freed with pool
+            self.device.destroy_descriptor_pool(table.pool, None);
+            self.device.destroy_descriptor_set_layout(table.layout, None);
+        }
+    }
+
+    // image is expected to be in SHADER_READ_ONLY_OPTIMAL when sampled
+    #[optimize(speed)]
+    pub fn bindless_add_texture(
+        &mut self,
+        table: &mut BindlessTable,
+        image: &Image,
+        sampler: vk::Sampler,
+    ) -> u32 {
+        let index =
+            table.slots[BindlessKind::Texture as usize].alloc(table.capacity, self.completed_frame);
+        self.bindless_write_image(
+            table,
+            BindlessKind::Texture,
+            index,
+            vk::DescriptorImageInfo {
+                sampler,
+                image_view: image.view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            },
+        );
+        index
+    }
+
+    // image is expected to be in GENERAL when accessed
+    #[optimize(speed)]
+    pub fn bindless_add_storage_image(&mut self, table: &mut BindlessTable, image: &Image) -> u32 {
+        let index = table.slots[BindlessKind::StorageImage as usize]
+            .alloc(table.capacity, self.completed_frame);
+        self.bindless_write_image(
+            table,
+            BindlessKind::StorageImage,
+            index,
+            vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view: image.view,
+                image_layout: vk::ImageLayout::GENERAL,
+            },
+        );
+        index
+    }
+
+    #[optimize(speed)]
+    pub fn bindless_add_buffer(&mut self, table: &mut BindlessTable, range: BufferRange) -> u32 {
+        let index = table.slots[BindlessKind::StorageBuffer as usize]
+            .alloc(table.capacity, self.completed_frame);
+        let buffer_info = vk::DescriptorBufferInfo {
+            buffer: range.buffer,
+            offset: range.offset,
+            range: range.size,
+        };
+        let write = vk::WriteDescriptorSet {
+            dst_set: table.set,
+            dst_binding: BindlessKind::StorageBuffer.binding(),
+            dst_array_element: index,
+            descriptor_count: 1,
+            descriptor_type: BindlessKind::StorageBuffer.descriptor_type(),
+            p_buffer_info: &buffer_info,
+            ..Default::default()
+        };
+        unsafe { self.device.update_descriptor_sets(&[write], &[]) };
+        index
+    }
+
+    // slot keeps pointing to old resource (nothing should read it) and is reused after frames in flight are done
+    // so resource itself can be destroyed right after removing it, as long as it goes through deletion queue / wait
+    #[optimize(speed)]
+    pub fn bindless_remove(&mut self, table: &mut BindlessTable, kind: BindlessKind, index: u32) {
+        table.slots[kind as usize].remove(index, self.frame);
+    }
+
+    fn bindless_write_image(
+        &self,
+        table: &BindlessTable,
+        kind: BindlessKind,
+        index: u32,
+        image_info: vk::DescriptorImageInfo,
+    ) {
+        assert!(image_info.image_view != vk::ImageView::null());
+        let write = vk::WriteDescriptorSet {
+            dst_set: table.set,
+            dst_binding: kind.binding(),
+            dst_array_element: index,
+            descriptor_count: 1,
+            descriptor_type: kind.descriptor_type(),
+            p_image_info: &image_info,
+            ..Default::default()
+        };
+        unsafe { self.device.update_descriptor_sets(&[write], &[]) };
+    }
+}
@@ -2,25 +2,82 @@ fn empty_arr<T>() -> &'static [T] {
     &[]
 }
 
+use crate::barriers::{BarrierBatch, ImageState, ImageUsage};
 use crate::{Image, Renderer};
 use ash::vk::{self, MemoryBarrier};
 
+// every layer of first mip, which is what whole-image copies touch
+fn first_mip_subresources(image: &Image) -> impl Iterator<Item = vk::ImageSubresourceRange> + '_ {
+    (0..image.layers).map(|layer| image.subresource_range(0, 1, layer, 1))
+}
+
+// images whose first mip is entirely in GENERAL stay there, everything else goes to *_OPTIMAL transfer layout
+fn transfer_state(image: &Image, write: bool) -> ImageState {
+    let mut state = if write {
+        ImageUsage::TransferDst.state()
+    } else {
+        ImageUsage::TransferSrc.state()
+    };
+    if (0..image.layers).all(|layer| image.state(0, layer).layout == vk::ImageLayout::GENERAL) {
+        state.layout = vk::ImageLayout::GENERAL;
+    }
+    state
+}
+
 impl Renderer {
+    // copies and blits take &Image, so tracked states can not change. Instead every subresource
+    // goes from its own tracked state into transfer state, and back to it afterwards
+    fn cmd_enter_transfer(&self, cmdbuf: &vk::CommandBuffer, image: &Image, transfer: ImageState) {
+        let mut batch = BarrierBatch::new();
+        for range in first_mip_subresources(image) {
+            let state = image.state(range.base_mip_level, range.base_array_layer);
+            // there would be nothing to return to
+            assert!(
+                state.layout != vk::ImageLayout::UNDEFINED
+                    && state.layout != vk::ImageLayout::PREINITIALIZED,
+                "Image used in copy / blit has to be transitioned out of {:?} first",
+                state.layout
+            );
+            if state.needs_barrier_to(&transfer) {
+                batch.image_range(image, range, state, transfer);
+            }
+        }
+        self.cmd_flush_barriers(cmdbuf, &mut batch);
+    }
+
+    fn cmd_leave_transfer(&self, cmdbuf: &vk::CommandBuffer, image: &Image, transfer: ImageState) {
+        let mut batch = BarrierBatch::new();
+        for range in first_mip_subresources(image) {
+            let state = image.state(range.base_mip_level, range.base_array_layer);
+            if transfer.needs_barrier_to(&state) {
+                batch.image_range(image, range, transfer, state);
+            }
+        }
+        self.cmd_flush_barriers(cmdbuf, &mut batch);
+    }
+
+    // first mip of every layer. Images have to have same number of layers
     #[cold]
     #[optimize(speed)]
     pub fn copy_whole_image(&self, cmdbuf: vk::CommandBuffer, src: &Image, dst: &Image) {
+        assert!(src.layers == dst.layers);
+        let src_transfer = transfer_state(src, false);
+        let dst_transfer = transfer_state(dst, true);
+        self.cmd_enter_transfer(&cmdbuf, src, src_transfer);
+        self.cmd_enter_transfer(&cmdbuf, dst, dst_transfer);
+
         let copy_op = vk::ImageCopy {
             dst_subresource: vk::ImageSubresourceLayers {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
+                aspect_mask: dst.aspect,
                 mip_level: 0,
                 base_array_layer: 0,
-                layer_count: 1,
+                layer_count: dst.layers,
             },
             src_subresource: vk::ImageSubresourceLayers {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
+                aspect_mask: src.aspect,
                 mip_level: 0,
                 base_array_layer: 0,
-                layer_count: 1,
+                layer_count: src.layers,
             },
             src_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
             dst_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
@@ -32,44 +89,19 @@ impl Renderer {
             self.device.cmd_copy_image(
                 cmdbuf,
                 src.image,
-                vk::ImageLayout::GENERAL, // TODO
+                src_transfer.layout,
                 dst.image,
-                vk::ImageLayout::GENERAL, // TODO
+                dst_transfer.layout,
                 &[copy_op],
             );
-
-            let barrier: vk::ImageMemoryBarrier = vk::ImageMemoryBarrier {
-                s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
-                old_layout: vk::ImageLayout::GENERAL,
-                new_layout: vk::ImageLayout::GENERAL,
-                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                image: dst.image, // assume you have the image handle
-                subresource_range: vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::COLOR,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                },
-                src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-                dst_access_mask: vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
-                ..Default::default() // initialize other fields if necessary
-            };
-
-            self.device.cmd_pipeline_barrier(
-                cmdbuf,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::COMPUTE_SHADER,
-                vk::DependencyFlags::empty(),
-                empty_arr::<vk::MemoryBarrier>(),
-                empty_arr::<vk::BufferMemoryBarrier>(),
-                &[barrier],
-            );
         }
+
+        self.cmd_leave_transfer(&cmdbuf, src, src_transfer);
+        self.cmd_leave_transfer(&cmdbuf, dst, dst_transfer);
     }
 
     // basically copy image into another image (with possible dimension mismatch and thus scaling)
+    // first mip of every layer. Images have to have same number of layers
     #[cold]
     #[optimize(speed)]
     pub fn blit_whole_image(
@@ -79,6 +111,12 @@ impl Renderer {
         dst: &Image,
         filter: vk::Filter,
     ) {
+        assert!(src.layers == dst.layers);
+        let src_transfer = transfer_state(src, false);
+        let dst_transfer = transfer_state(dst, true);
+        self.cmd_enter_transfer(&cmdbuf, src, src_transfer);
+        self.cmd_enter_transfer(&cmdbuf, dst, dst_transfer);
+
         let src_offsets = [
             vk::Offset3D { x: 0, y: 0, z: 0 },
             vk::Offset3D {
@@ -99,16 +137,16 @@ impl Renderer {
 
         let blit_op = vk::ImageBlit {
             src_subresource: vk::ImageSubresourceLayers {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
+                aspect_mask: src.aspect,
                 mip_level: 0,
                 base_array_layer: 0,
-                layer_count: 1,
+                layer_count: src.layers,
             },
             dst_subresource: vk::ImageSubresourceLayers {
-                aspect_mask: vk::ImageAspectFlags::COLOR,
+                aspect_mask: dst.aspect,
                 mip_level: 0,
                 base_array_layer: 0,
-                layer_count: 1,
+                layer_count: dst.layers,
             },
             src_offsets: src_offsets,
             dst_offsets: dst_offsets,
@@ -119,41 +157,16 @@ impl Renderer {
             self.device.cmd_blit_image(
                 cmdbuf,
                 src.image,
-                vk::ImageLayout::GENERAL, // TODO
+                src_transfer.layout,
                 dst.image,
-                vk::ImageLayout::GENERAL, // TODO
+                dst_transfer.layout,
                 &[blit_op],
                 filter,
             );
-
-            let barrier = vk::ImageMemoryBarrier {
-                old_layout: vk::ImageLayout::GENERAL,
-                new_layout: vk::ImageLayout::GENERAL,
-                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-                image: dst.image,
-                subresource_range: vk::ImageSubresourceRange {
-                    aspect_mask: vk::ImageAspectFlags::COLOR,
-                    base_mip_level: 0,
-                    level_count: 1,
-                    base_array_layer: 0,
-                    layer_count: 1,
-                },
-                src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
-                dst_access_mask: vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
-                ..Default::default()
-            };
-
-            self.device.cmd_pipeline_barrier(
-                cmdbuf,
-                vk::PipelineStageFlags::TRANSFER,
-                vk::PipelineStageFlags::COMPUTE_SHADER,
-                vk::DependencyFlags::empty(),
-                empty_arr::<vk::MemoryBarrier>(),
-                empty_arr::<vk::BufferMemoryBarrier>(),
-                &[barrier],
-            );
         }
+
+        self.cmd_leave_transfer(&cmdbuf, src, src_transfer);
+        self.cmd_leave_transfer(&cmdbuf, dst, dst_transfer);
     }
 
     // finds first image format that is supported by device
@@ -165,6 +178,26 @@ impl Renderer {
         ty: vk::ImageType,
         tiling: vk::ImageTiling,
         usage: vk::ImageUsageFlags,
+    ) -> Option<vk::Format> {
+        self.find_supported_format_with_flags(
+            candidates,
+            ty,
+            tiling,
+            usage,
+            vk::ImageCreateFlags::empty(),
+        )
+    }
+
+    // same, for images created with flags (e.g. CUBE_COMPATIBLE)
+    #[cold]
+    #[optimize(size)]
+    pub fn find_supported_format_with_flags(
+        &self,
+        candidates: &[vk::Format],
+        ty: vk::ImageType,
+        tiling: vk::ImageTiling,
+        usage: vk::ImageUsageFlags,
+        flags: vk::ImageCreateFlags,
     ) -> Option<vk::Format> {
         for &format in candidates {
             let result = unsafe {
@@ -174,7 +207,7 @@ impl Renderer {
                     ty,
                     tiling,
                     usage,
-                    vk::ImageCreateFlags::empty(),
+                    flags,
                 )
             };
 
@@ -1,19 +1,26 @@
-use crate::{atrace, ring::Ring, Buffer, Renderer}; // Import the LumalRenderer struct
+use crate::{atrace, ring::Ring, Buffer, MemoryLocation, Renderer}; // Import the LumalRenderer struct
 use ash::vk::{self, BufferUsageFlags};
+use std::marker::PhantomData;
 use std::ptr::{self, copy_nonoverlapping};
 
 use gpu_allocator::vulkan::{self as vma, AllocationCreateDesc};
 
 impl Renderer {
-    // creates a GPU buffer
+    // creates a GPU buffer. Everything except GpuOnly is persistently mapped
     #[cold]
     #[optimize(size)]
     pub fn create_buffer(
         &mut self,
         usage: vk::BufferUsageFlags,
         size: usize,
-        host: bool,
+        location: MemoryLocation,
     ) -> Buffer {
+        let usage = if self.vulkan_data.buffer_device_address {
+            usage | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS
+        } else {
+            usage
+        };
+
         let buffer_info = vk::BufferCreateInfo {
             flags: vk::BufferCreateFlags::empty(),
             size: size as vk::DeviceSize,
@@ -23,18 +30,26 @@ impl Renderer {
             ..Default::default()
         };
 
-        let location = if host {
-            gpu_allocator::MemoryLocation::CpuToGpu
-        } else {
-            gpu_allocator::MemoryLocation::GpuOnly
-        };
-
         let vk_buffer = unsafe { self.device.create_buffer(&buffer_info, None) }.unwrap();
-        let requirements = unsafe { self.device.get_buffer_memory_requirements(vk_buffer) };
+        let mut requirements = unsafe { self.device.get_buffer_memory_requirements(vk_buffer) };
+
+        let vma_location = match location {
+            MemoryLocation::GpuOnly => gpu_allocator::MemoryLocation::GpuOnly,
+            MemoryLocation::CpuToGpu => gpu_allocator::MemoryLocation::CpuToGpu,
+            MemoryLocation::GpuToCpu => gpu_allocator::MemoryLocation::GpuToCpu,
+            MemoryLocation::DeviceHostVisible => {
+                // allocator has no such location, so we narrow allowed memory types instead
+                let device_host_types = self.device_host_visible_memory_types();
+                if requirements.memory_type_bits & device_host_types != 0 {
+                    requirements.memory_type_bits &= device_host_types;
+                }
+                gpu_allocator::MemoryLocation::CpuToGpu
+            }
+        };
 
         let alloc_info = vma::AllocationCreateDesc {
             requirements: requirements,
-            location,
+            location: vma_location,
             allocation_scheme: vma::AllocationScheme::GpuAllocatorManaged,
             linear: true, // buffers are always linear
             name: "",
@@ -49,15 +64,52 @@ impl Renderer {
                 .unwrap()
         };
 
+        let address = if self.vulkan_data.buffer_device_address {
+            let address_info = vk::BufferDeviceAddressInfo {
+                buffer: vk_buffer,
+                ..Default::default()
+            };
+            unsafe { self.device.get_buffer_device_address(&address_info) }
+        } else {
+            0
+        };
+
         // TODO: Integrated CPU memory utilization
         // TODO: what if it fails? Different set of flags?
         Buffer {
             buffer: vk_buffer,
             allocation,
+            size: size as vk::DeviceSize,
+            location,
+            usage,
+            views: vec![],
+            address,
             // mapped,
         }
     }
 
+    // bitmask of memory types that are both DEVICE_LOCAL and HOST_VISIBLE (and coherent, allocator wants it)
+    fn device_host_visible_memory_types(&self) -> u32 {
+        let properties = unsafe {
+            self.instance
+                .get_physical_device_memory_properties(self.vulkan_data.physical_device)
+        };
+        let wanted = vk::MemoryPropertyFlags::DEVICE_LOCAL
+            | vk::MemoryPropertyFlags::HOST_VISIBLE
+            | vk::MemoryPropertyFlags::HOST_COHERENT;
+        properties
+            .memory_types_as_slice()
+            .iter()
+            .enumerate()
+            .fold(0, |mask, (i, memory_type)| {
+                if memory_type.property_flags.contains(wanted) {
+                    mask | (1 << i)
+                } else {
+                    mask
+                }
+            })
+    }
+
     // creates ring of vulkan buffers. Optionally maps
     #[cold]
     #[optimize(size)]
@@ -66,9 +118,11 @@ impl Renderer {
         ring_size: usize,
         usage: vk::BufferUsageFlags,
         biffer_size: usize,
-        host: bool,
+        location: MemoryLocation,
     ) -> Ring<Buffer> {
-        (0..ring_size).map(|_| self.create_buffer(usage, biffer_size, host)).collect()
+        (0..ring_size)
+            .map(|_| self.create_buffer(usage, biffer_size, location))
+            .collect()
     }
 
     #[cold]
@@ -80,11 +134,61 @@ impl Renderer {
             //     Some(_) => self.device.unmap_memory(buf.allocation.memory()),
             //     None => {} // do nothing
             // }
+            for view in &buf.views {
+                self.device.destroy_buffer_view(*view, None);
+            }
             self.allocator.free(buf.allocation).unwrap();
             self.device.destroy_buffer(buf.buffer, None);
         };
     }
 
+    // creates texel buffer view. It is owned by buffer and destroyed with it
+    // range can be vk::WHOLE_SIZE. Descriptors pick view with DescriptorInfo::buffer_view
+    #[cold]
+    #[optimize(size)]
+    pub fn create_buffer_view(
+        &self,
+        buffer: &mut Buffer,
+        format: vk::Format,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    ) -> vk::BufferView {
+        assert!(
+            buffer.usage.intersects(
+                vk::BufferUsageFlags::UNIFORM_TEXEL_BUFFER
+                    | vk::BufferUsageFlags::STORAGE_TEXEL_BUFFER
+            ),
+            "Buffer views need UNIFORM_TEXEL_BUFFER or STORAGE_TEXEL_BUFFER usage"
+        );
+        assert!(range == vk::WHOLE_SIZE || offset + range <= buffer.size);
+
+        let view_info = vk::BufferViewCreateInfo {
+            buffer: buffer.buffer,
+            format,
+            offset,
+            range,
+            ..Default::default()
+        };
+        let view = unsafe { self.device.create_buffer_view(&view_info, None) }.unwrap();
+        buffer.views.push(view);
+        view
+    }
+
+    // same view for every buffer in ring (e.g. per-frame texel buffers)
+    #[cold]
+    #[optimize(size)]
+    pub fn create_buffer_ring_views(
+        &self,
+        buffers: &mut Ring<Buffer>,
+        format: vk::Format,
+        offset: vk::DeviceSize,
+        range: vk::DeviceSize,
+    ) {
+        for buffer in buffers.as_mut_slice() {
+            self.create_buffer_view(buffer, format, offset, range);
+        }
+    }
+
     #[cold]
     #[optimize(size)]
     pub fn destroy_buffer_ring(&mut self, buffers: Ring<Buffer>) {
@@ -95,6 +199,7 @@ impl Renderer {
 
     // creates a GPU buffer and copies elements into it
     // does buffer_usage |= TRANSFER_DST automatically
+    // waits for GPU, use cmd_upload_buffer inside of frame
     #[cold]
     #[optimize(size)]
     pub fn create_and_upload_buffer<T>(
@@ -104,33 +209,131 @@ impl Renderer {
     ) -> Buffer {
         buffer_usage |= vk::BufferUsageFlags::TRANSFER_DST;
 
-        let count = elements.len();
         let size = std::mem::size_of_val(elements);
-        let buffer = self.create_buffer(
-            buffer_usage,
-            size,
-            false, // TODO: bool -> Enum
+        let buffer = self.create_buffer(buffer_usage, size, MemoryLocation::GpuOnly);
+
+        let cmdbuf = self.begin_single_time_command_buffer();
+        self.begin_single_time_staging();
+        self.cmd_upload_buffer(&cmdbuf, &buffer, 0, elements);
+        self.end_single_time_command_buffer(cmdbuf);
+        self.end_single_time_staging();
+
+        buffer
+    }
+    // create elem ring not implemented.
+}
+
+/// Plain old data, safe to reinterpret from whatever bytes GPU wrote.
+/// bool, enums, char, references and NonZero* are Copy but not Pod.
+///
+/// # Safety
+/// Every bit pattern must be a valid T and T must have no padding.
+/// For your #[repr(C)] structs: `unsafe impl Pod for Vertex {}`
+pub unsafe trait Pod: Copy + 'static {}
+
+macro_rules! impl_pod {
+    ($($t:ty),*) => { $(unsafe impl Pod for $t {})* };
+}
+impl_pod!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64);
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+
+// persistently mapped buffer of T's. Offsets and lengths are in elements, not bytes
+// create with Renderer::create_typed_buffer, destroy with Renderer::destroy_typed_buffer
+// memory is always HOST_COHERENT (allocator only hands out coherent host memory), so no flushes
+pub struct TypedBuffer<T: Pod> {
+    pub buffer: Buffer,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Pod> TypedBuffer<T> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn mapped_ptr(&self) -> *mut T {
+        self.buffer.allocation.mapped_ptr().unwrap().as_ptr() as *mut T
+    }
+
+    // copies data to [offset, offset + data.len())
+    pub fn write(&mut self, offset: usize, data: &[T]) {
+        assert!(
+            offset + data.len() <= self.len,
+            "Write of {} elements at {} out of bounds (len {})",
+            data.len(),
+            offset,
+            self.len
         );
+        unsafe { copy_nonoverlapping(data.as_ptr(), self.mapped_ptr().add(offset), data.len()) };
+    }
 
-        let staging_buffer = self.create_buffer(BufferUsageFlags::TRANSFER_SRC, size, true);
+    // what GPU wrote (once it is done writing, which is on you to ensure)
+    pub fn read(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.mapped_ptr(), self.len) }
+    }
 
-        unsafe {
-            copy_nonoverlapping(
-                elements.as_ptr(),
-                staging_buffer.allocation.mapped_ptr().unwrap().as_ptr() as *mut T,
-                count,
-            );
-        }
+    pub fn map_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.mapped_ptr(), self.len) }
+    }
+}
+
+impl Renderer {
+    // buffer of len T's. Location has to be host visible (anything but GpuOnly)
+    #[cold]
+    #[optimize(size)]
+    pub fn create_typed_buffer<T: Pod>(
+        &mut self,
+        usage: vk::BufferUsageFlags,
+        len: usize,
+        location: MemoryLocation,
+    ) -> TypedBuffer<T> {
+        assert!(location.is_mapped(), "TypedBuffer has to be host visible");
+        assert!(size_of::<T>() != 0);
 
-        self.copy_buffer_to_buffer_single_time(
-            staging_buffer.buffer,
-            buffer.buffer,
-            size as vk::DeviceSize,
+        let buffer = self.create_buffer(usage, len * size_of::<T>(), location);
+        let mapped = buffer.allocation.mapped_ptr().expect("Buffer memory is not mapped");
+        assert!(
+            mapped.as_ptr().cast::<T>().is_aligned(),
+            "Mapped memory is not aligned for {}",
+            std::any::type_name::<T>()
         );
 
-        self.destroy_buffer(staging_buffer);
+        assert!(
+            buffer
+                .allocation
+                .memory_properties()
+                .contains(vk::MemoryPropertyFlags::HOST_COHERENT),
+            "TypedBuffer memory is not HOST_COHERENT"
+        );
 
-        buffer
+        TypedBuffer {
+            buffer,
+            len,
+            _marker: PhantomData,
+        }
+    }
+
+    // creates typed buffer and fills it with elements
+    #[cold]
+    #[optimize(size)]
+    pub fn create_and_write_typed_buffer<T: Pod>(
+        &mut self,
+        elements: &[T],
+        usage: vk::BufferUsageFlags,
+        location: MemoryLocation,
+    ) -> TypedBuffer<T> {
+        let mut typed = self.create_typed_buffer(usage, elements.len(), location);
+        typed.write(0, elements);
+        typed
+    }
+
+    #[cold]
+    #[optimize(size)]
+    pub fn destroy_typed_buffer<T: Pod>(&mut self, typed: TypedBuffer<T>) {
+        self.destroy_buffer(typed.buffer);
     }
-    // create elem ring not implemented.
 }
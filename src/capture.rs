@@ -0,0 +1,393 @@
+// screenshots and frame sequences. Swapchain image is copied at the end of the frame,
+// read back once its fence signals and then converted + written to disk on writer thread
+// (so capturing does not stall rendering)
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+
+use crate::barriers::{BarrierBatch, ImageState};
+use crate::readback::Readback;
+use crate::{Image, Renderer};
+use ash::vk;
+
+pub struct CaptureRequest {
+    pub path: PathBuf,
+    pub frames_left: u32,
+    pub sequence: Option<u32>, // index of next frame in sequence, None for single capture
+}
+
+pub struct PendingCapture {
+    pub readback: Readback<u8>,
+    pub format: vk::Format,
+    pub width: u32,
+    pub height: u32,
+    pub path: PathBuf,
+}
+
+// raw pixels on their way to disk
+struct CaptureJob {
+    data: Vec<u8>,
+    format: vk::Format,
+    width: u32,
+    height: u32,
+    path: PathBuf,
+}
+
+// thread that converts and saves captures. Started on first capture, stopped by finish_captures
+#[derive(Default)]
+pub struct CaptureWriter {
+    sender: Option<mpsc::Sender<CaptureJob>>,
+    // returns every capture that failed to save
+    thread: Option<JoinHandle<Vec<(PathBuf, std::io::Error)>>>,
+}
+
+impl CaptureWriter {
+    fn send(&mut self, job: CaptureJob) {
+        let sender = self.sender.get_or_insert_with(|| {
+            let (sender, receiver) = mpsc::channel::<CaptureJob>();
+            self.thread = Some(std::thread::spawn(move || {
+                let mut errors = vec![];
+                for job in receiver {
+                    let rgba = convert_to_rgba8(&job.data, job.format);
+                    if let Err(e) = save_rgba8(&job.path, job.width, job.height, &rgba) {
+                        errors.push((job.path, e));
+                    }
+                }
+                errors
+            }));
+            sender
+        });
+        sender.send(job).expect("Capture writer thread died");
+    }
+
+    // waits until everything sent so far is on disk
+    fn finish(&mut self) -> Vec<(PathBuf, std::io::Error)> {
+        self.sender = None; // closes channel, so thread exits after last job
+        match self.thread.take() {
+            Some(thread) => thread.join().unwrap_or_else(|_| {
+                vec![(
+                    PathBuf::new(),
+                    std::io::Error::other("capture writer thread panicked"),
+                )]
+            }),
+            None => vec![],
+        }
+    }
+}
+
+// "shot.png" + 7 -> "shot_0007.png", or "shot_{}.png" + 7 -> "shot_0007.png"
+fn sequence_path(path: &Path, index: u32) -> PathBuf {
+    let path_str = path.to_string_lossy();
+    if path_str.contains("{}") {
+        return PathBuf::from(path_str.replacen("{}", &format!("{:04}", index), 1));
+    }
+    let stem = path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default();
+    let name = match path.extension() {
+        Some(extension) => format!("{}_{:04}.{}", stem, index, extension.to_string_lossy()),
+        None => format!("{}_{:04}", stem, index),
+    };
+    path.with_file_name(name)
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+// float formats hold linear values, so they are encoded to sRGB to look the same as on screen
+fn linear_to_srgb8(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0); // NaN -> 0 too
+    let srgb = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (srgb * 255.0 + 0.5) as u8
+}
+
+fn unorm8(value: u32, bits: u32) -> u8 {
+    (value * 255 / ((1 << bits) - 1)) as u8
+}
+
+// converts tightly packed pixels of supported formats into 8 bit RGBA
+// UNORM and SRGB bytes are copied as they are - that is what ends up on screen
+#[cold]
+#[optimize(size)]
+pub fn convert_to_rgba8(data: &[u8], format: vk::Format) -> Vec<u8> {
+    use vk::Format as F;
+    match format {
+        F::R8G8B8A8_UNORM | F::R8G8B8A8_SRGB => data.to_vec(),
+        F::B8G8R8A8_UNORM | F::B8G8R8A8_SRGB => data
+            .chunks_exact(4)
+            .flat_map(|texel| [texel[2], texel[1], texel[0], texel[3]])
+            .collect(),
+        F::A2B10G10R10_UNORM_PACK32 | F::A2R10G10B10_UNORM_PACK32 => data
+            .chunks_exact(4)
+            .flat_map(|texel| {
+                let packed = u32::from_le_bytes(texel.try_into().unwrap());
+                let low = unorm8(packed & 0x3ff, 10);
+                let g = unorm8((packed >> 10) & 0x3ff, 10);
+                let high = unorm8((packed >> 20) & 0x3ff, 10);
+                let a = unorm8(packed >> 30, 2);
+                if format == F::A2B10G10R10_UNORM_PACK32 {
+                    [low, g, high, a]
+                } else {
+                    [high, g, low, a]
+                }
+            })
+            .collect(),
+        F::R16G16B16A16_UNORM => data
+            .chunks_exact(2)
+            .map(|c| (u16::from_le_bytes([c[0], c[1]]) >> 8) as u8)
+            .collect(),
+        F::R16G16B16A16_SFLOAT => data
+            .chunks_exact(8)
+            .flat_map(|texel| {
+                let channel =
+                    |c: usize| f16_to_f32(u16::from_le_bytes([texel[c * 2], texel[c * 2 + 1]]));
+                [
+                    linear_to_srgb8(channel(0)),
+                    linear_to_srgb8(channel(1)),
+                    linear_to_srgb8(channel(2)),
+                    (channel(3).clamp(0.0, 1.0) * 255.0 + 0.5) as u8,
+                ]
+            })
+            .collect(),
+        F::R32G32B32A32_SFLOAT => data
+            .chunks_exact(16)
+            .flat_map(|texel| {
+                let channel =
+                    |c: usize| f32::from_le_bytes(texel[c * 4..c * 4 + 4].try_into().unwrap());
+                [
+                    linear_to_srgb8(channel(0)),
+                    linear_to_srgb8(channel(1)),
+                    linear_to_srgb8(channel(2)),
+                    (channel(3).clamp(0.0, 1.0) * 255.0 + 0.5) as u8,
+                ]
+            })
+            .collect(),
+        _ => panic!("Capturing {:?} images is not supported", format),
+    }
+}
+
+// writes PNG or binary PPM (P6, alpha is dropped), picked by extension. PNG is default
+#[cold]
+#[optimize(size)]
+pub fn save_rgba8(path: &Path, width: u32, height: u32, rgba: &[u8]) -> std::io::Result<()> {
+    let file = std::fs::File::create(path)?;
+    let mut writer = std::io::BufWriter::new(file);
+
+    let is_ppm = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("ppm"));
+    if is_ppm {
+        write!(writer, "P6\n{} {}\n255\n", width, height)?;
+        let rgb: Vec<u8> = rgba.chunks_exact(4).flat_map(|t| [t[0], t[1], t[2]]).collect();
+        writer.write_all(&rgb)?;
+        writer.flush()
+    } else {
+        let mut encoder = png::Encoder::new(writer, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut png_writer = encoder.write_header()?;
+        png_writer.write_image_data(rgba)?;
+        png_writer.finish()?;
+        Ok(())
+    }
+}
+
+impl Renderer {
+    // captures swapchain image of next frame (recorded in end_frame) into PNG or PPM
+    // headless renderer has no swapchain, use capture_image on render target instead
+    #[cold]
+    #[optimize(size)]
+    pub fn capture_frame<P: AsRef<Path>>(&mut self, path: P) {
+        self.assert_can_capture_frames();
+        self.capture_request = Some(CaptureRequest {
+            path: path.as_ref().to_path_buf(),
+            frames_left: 1,
+            sequence: None,
+        });
+    }
+
+    // captures next count frames. Path gets frame index, either in place of "{}" or before extension
+    #[cold]
+    #[optimize(size)]
+    pub fn capture_frames<P: AsRef<Path>>(&mut self, path: P, count: u32) {
+        self.assert_can_capture_frames();
+        self.capture_request = Some(CaptureRequest {
+            path: path.as_ref().to_path_buf(),
+            frames_left: count,
+            sequence: Some(0),
+        });
+    }
+
+    fn assert_can_capture_frames(&self) {
+        assert!(
+            !self.vulkan_data.headless,
+            "Headless renderer has no swapchain frames to capture, use capture_image"
+        );
+    }
+
+    // captures any image (mip 0, layer 0) right away. Waits for GPU
+    // image is left in TransferSrc
+    #[cold]
+    #[optimize(size)]
+    pub fn capture_image<P: AsRef<Path>>(&mut self, image: &mut Image, path: P) {
+        let path = path.as_ref();
+        let data = self.download_image(image, 0, 0);
+        let rgba = convert_to_rgba8(&data, image.format);
+        save_rgba8(path, image.extent.width, image.extent.height, &rgba)
+            .unwrap_or_else(|e| panic!("Failed to write capture {}: {}", path.display(), e));
+    }
+
+    // called from end_frame. Expects swapchain image to be in PRESENT_SRC_KHR (as rpass leaves it)
+    #[cold]
+    #[optimize(size)]
+    pub(crate) fn record_frame_capture(&mut self, cmdbuf: &vk::CommandBuffer) {
+        let Some(request) = &mut self.capture_request else {
+            return;
+        };
+        assert!(
+            self.vulkan_data.swapchain_usage.contains(vk::ImageUsageFlags::TRANSFER_SRC),
+            "Surface does not support copying from swapchain images"
+        );
+
+        let path = match request.sequence {
+            Some(index) => {
+                request.sequence = Some(index + 1);
+                sequence_path(&request.path, index)
+            }
+            None => request.path.clone(),
+        };
+        request.frames_left -= 1;
+        if request.frames_left == 0 {
+            self.capture_request = None;
+        }
+
+        let present = ImageState {
+            layout: vk::ImageLayout::PRESENT_SRC_KHR,
+            access: vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            stage: vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+        };
+        let transfer_src = ImageState {
+            layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            access: vk::AccessFlags2::TRANSFER_READ,
+            stage: vk::PipelineStageFlags2::TRANSFER,
+        };
+        // presentation engine does not need any access, it waits on semaphore
+        let back_to_present = ImageState {
+            access: vk::AccessFlags2::NONE,
+            stage: vk::PipelineStageFlags2::BOTTOM_OF_PIPE,
+            ..present
+        };
+
+        // swapchain images are not tracked, so barriers are explicit
+        let index = self.image_index as usize;
+        let image = &self.vulkan_data.swapchain_images[index];
+        let (vk_image, format, aspect, extent) =
+            (image.image, image.format, image.aspect, image.extent);
+
+        let mut batch = BarrierBatch::new();
+        batch.image(image, present, transfer_src);
+        self.cmd_flush_barriers(cmdbuf, &mut batch);
+
+        let readback = self.cmd_copy_image_to_readback(
+            cmdbuf,
+            vk_image,
+            format,
+            extent,
+            vk::ImageSubresourceLayers {
+                aspect_mask: aspect,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+        );
+
+        let image = &self.vulkan_data.swapchain_images[index];
+        batch.image(image, transfer_src, back_to_present);
+        self.cmd_flush_barriers(cmdbuf, &mut batch);
+
+        self.pending_captures.push(PendingCapture {
+            readback,
+            format,
+            width: extent.width,
+            height: extent.height,
+            path,
+        });
+    }
+
+    // writes captures whose frames are done. Called from end_frame
+    #[cold]
+    #[optimize(size)]
+    pub(crate) fn write_ready_captures(&mut self) {
+        let mut i = 0;
+        while i < self.pending_captures.len() {
+            if self.is_readback_ready(&self.pending_captures[i].readback) {
+                let capture = self.pending_captures.swap_remove(i);
+                self.write_capture(capture);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    // waits for GPU and writer thread, so every pending capture is on disk after this
+    // returns captures that could not be written
+    #[cold]
+    #[optimize(size)]
+    pub fn finish_captures(&mut self) -> Vec<(PathBuf, std::io::Error)> {
+        if !self.pending_captures.is_empty() {
+            unsafe { self.device.device_wait_idle().unwrap() };
+            for capture in std::mem::take(&mut self.pending_captures) {
+                let data = self.read_and_destroy_staging(capture.readback);
+                self.send_capture(
+                    data,
+                    capture.format,
+                    capture.width,
+                    capture.height,
+                    capture.path,
+                );
+            }
+        }
+
+        self.capture_writer.finish()
+    }
+
+    // only copies pixels out of staging buffer, conversion and IO happen on writer thread
+    fn write_capture(&mut self, capture: PendingCapture) {
+        let data = self.take_readback(capture.readback);
+        self.send_capture(
+            data,
+            capture.format,
+            capture.width,
+            capture.height,
+            capture.path,
+        );
+    }
+
+    fn send_capture(
+        &mut self,
+        data: Vec<u8>,
+        format: vk::Format,
+        width: u32,
+        height: u32,
+        path: PathBuf,
+    ) {
+        self.capture_writer.send(CaptureJob {
+            data,
+            format,
+            width,
+            height,
+            path,
+        });
+    }
+}
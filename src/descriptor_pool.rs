@@ -0,0 +1,172 @@
+// growable descriptor pool allocator
+// starts with one pool sized from DescriptorCounter (see flush_descriptor_setup),
+// then creates more pools when current one runs out, so pipes can be created whenever
+
+use std::collections::HashMap;
+
+use crate::Renderer;
+use ash::{vk, Device};
+
+// sets in first pool created without announced descriptors. Each next pool is twice bigger
+pub const DEFAULT_POOL_SETS: u32 = 64;
+// descriptors of every type per set in pools that are not sized from DescriptorCounter
+pub const DEFAULT_DESCRIPTORS_PER_SET: u32 = 4;
+const MAX_POOL_SETS: u32 = 4096;
+
+const POOL_DESCRIPTOR_TYPES: [vk::DescriptorType; 11] = [
+    vk::DescriptorType::SAMPLER,
+    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+    vk::DescriptorType::SAMPLED_IMAGE,
+    vk::DescriptorType::STORAGE_IMAGE,
+    vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+    vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+    vk::DescriptorType::UNIFORM_BUFFER,
+    vk::DescriptorType::STORAGE_BUFFER,
+    vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+    vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+    vk::DescriptorType::INPUT_ATTACHMENT,
+];
+
+#[derive(Debug, Default)]
+pub struct DescriptorPoolAllocator {
+    pub pools: Vec<vk::DescriptorPool>,
+    current: usize, // pool new sets are allocated from. Older ones only get sets freed
+    next_pool_sets: u32,
+    // which pool every alive set came from, needed for freeing
+    set_pools: HashMap<vk::DescriptorSet, vk::DescriptorPool>,
+}
+
+impl DescriptorPoolAllocator {
+    // number of allocated (not freed) sets
+    pub fn allocated_sets(&self) -> usize {
+        self.set_pools.len()
+    }
+
+    // adds pool and makes it current
+    #[cold]
+    #[optimize(size)]
+    pub fn add_pool(
+        &mut self,
+        device: &Device,
+        pool_sizes: &[vk::DescriptorPoolSize],
+        max_sets: u32,
+    ) {
+        assert!(max_sets > 0);
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            max_sets,
+            flags: vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+            ..Default::default()
+        };
+        let pool = unsafe { device.create_descriptor_pool(&pool_info, None) }.unwrap();
+        self.pools.push(pool);
+        self.current = self.pools.len() - 1;
+    }
+
+    // generic pool, big enough for at least `required` descriptors (of layouts that failed to allocate)
+    #[cold]
+    #[optimize(size)]
+    fn grow(&mut self, device: &Device, required: &[vk::DescriptorPoolSize], required_sets: u32) {
+        let sets = self.next_pool_sets.max(DEFAULT_POOL_SETS).max(required_sets);
+        self.next_pool_sets = (sets * 2).min(MAX_POOL_SETS);
+
+        let pool_sizes: Vec<vk::DescriptorPoolSize> = POOL_DESCRIPTOR_TYPES
+            .iter()
+            .map(|&ty| {
+                let needed: u32 = required
+                    .iter()
+                    .filter(|size| size.ty == ty)
+                    .map(|size| size.descriptor_count)
+                    .sum();
+                vk::DescriptorPoolSize {
+                    ty,
+                    descriptor_count: (sets * DEFAULT_DESCRIPTORS_PER_SET).max(needed),
+                }
+            })
+            .collect();
+        self.add_pool(device, &pool_sizes, sets);
+    }
+
+    // allocates from current pool, creates new one if it is exhausted
+    // required is what these sets need in total, so new pool is guaranteed to fit them
+    // p_next is chained into vk::DescriptorSetAllocateInfo (e.g. variable descriptor count)
+    #[cold]
+    #[optimize(size)]
+    pub fn allocate(
+        &mut self,
+        device: &Device,
+        layouts: &[vk::DescriptorSetLayout],
+        required: &[vk::DescriptorPoolSize],
+        p_next: *const std::ffi::c_void,
+    ) -> Vec<vk::DescriptorSet> {
+        if self.pools.is_empty() {
+            self.grow(device, required, layouts.len() as u32);
+        }
+
+        let mut grown = false;
+        loop {
+            let pool = self.pools[self.current];
+            let alloc_info = vk::DescriptorSetAllocateInfo {
+                p_next,
+                descriptor_pool: pool,
+                descriptor_set_count: layouts.len() as u32,
+                p_set_layouts: layouts.as_ptr(),
+                ..Default::default()
+            };
+            match unsafe { device.allocate_descriptor_sets(&alloc_info) } {
+                Ok(sets) => {
+                    for set in &sets {
+                        self.set_pools.insert(*set, pool);
+                    }
+                    return sets;
+                }
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => {
+                    // fresh pool is sized for request, so failing twice is a bug
+                    assert!(!grown, "Descriptor sets do not fit even into new pool");
+                    self.grow(device, required, layouts.len() as u32);
+                    grown = true;
+                }
+                Err(err) => panic!("Failed to allocate descriptor sets: {err:?}"),
+            }
+        }
+    }
+
+    // sets are returned to pools they came from
+    #[cold]
+    #[optimize(size)]
+    pub fn free(&mut self, device: &Device, sets: &[vk::DescriptorSet]) {
+        let mut by_pool: HashMap<vk::DescriptorPool, Vec<vk::DescriptorSet>> = HashMap::new();
+        // null sets are ignored, like vkFreeDescriptorSets does
+        for set in sets.iter().filter(|set| **set != vk::DescriptorSet::null()) {
+            let pool = self
+                .set_pools
+                .remove(set)
+                .expect("Descriptor set was not allocated from this allocator (or freed twice)");
+            by_pool.entry(pool).or_default().push(*set);
+        }
+        for (pool, sets) in by_pool {
+            unsafe { device.free_descriptor_sets(pool, &sets) }.unwrap();
+        }
+    }
+
+    #[cold]
+    #[optimize(size)]
+    pub fn destroy(&mut self, device: &Device) {
+        for pool in self.pools.drain(..) {
+            unsafe { device.destroy_descriptor_pool(pool, None) };
+        }
+        self.set_pools.clear();
+        self.current = 0;
+        self.next_pool_sets = 0;
+    }
+}
+
+impl Renderer {
+    #[cold]
+    #[optimize(size)]
+    pub fn free_descriptor_sets(&mut self, sets: &[vk::DescriptorSet]) {
+        self.forget_descriptor_sets(sets);
+        self.vulkan_data.descriptor_pools.free(&self.device, sets);
+    }
+}
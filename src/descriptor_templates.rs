@@ -0,0 +1,304 @@
+// cached descriptor update templates - one per layout shape (and per pipeline layout for push descriptors)
+// data for template is packed array of DescriptorTemplateData, one element per descriptor, bindings in order
+// used by update_descriptors and cmd_push_descriptors, anything template can not express falls back to writes
+
+use std::collections::HashMap;
+
+use crate::descriptor_updates::DescriptorContents;
+use crate::descriptors::ShortDescriptorInfo;
+use crate::Renderer;
+use ash::vk;
+
+// everything that makes two set layouts "identically defined", so they can share template
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DescriptorShape {
+    // (binding number, type, count, stages, binding flags) for every binding
+    pub bindings: Vec<(
+        u32,
+        vk::DescriptorType,
+        u32,
+        vk::ShaderStageFlags,
+        vk::DescriptorBindingFlags,
+    )>,
+    pub flags: vk::DescriptorSetLayoutCreateFlags,
+}
+
+impl DescriptorShape {
+    pub fn new(infos: &[ShortDescriptorInfo], flags: vk::DescriptorSetLayoutCreateFlags) -> Self {
+        Self {
+            bindings: infos
+                .iter()
+                .enumerate()
+                .map(|(i, info)| {
+                    (
+                        info.binding.unwrap_or(i as u32),
+                        info.descriptor_type,
+                        info.count,
+                        info.stages,
+                        info.binding_flags,
+                    )
+                })
+                .collect(),
+            flags,
+        }
+    }
+
+    // partially bound / variable arrays might be written partially, template always writes everything
+    pub fn templatable(&self) -> bool {
+        self.bindings.iter().all(|(_, _, _, _, binding_flags)| binding_flags.is_empty())
+    }
+
+    // total descriptors, which is also length of template data
+    pub fn descriptor_count(&self) -> u32 {
+        self.bindings.iter().map(|(_, _, count, _, _)| count).sum()
+    }
+}
+
+// single element of template data. Same size for every kind, so stride is always the same
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union DescriptorTemplateData {
+    pub image: vk::DescriptorImageInfo,
+    pub buffer: vk::DescriptorBufferInfo,
+    pub texel_buffer_view: vk::BufferView,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct TemplateKey {
+    shape: DescriptorShape,
+    // pipeline layout, bind point and set index for push descriptor templates
+    push: Option<(vk::PipelineLayout, vk::PipelineBindPoint, u32)>,
+}
+
+#[derive(Debug, Default)]
+pub struct DescriptorTemplateCache {
+    templates: HashMap<TemplateKey, vk::DescriptorUpdateTemplate>,
+    // recorded in create_descriptor_set_layout
+    layout_shapes: HashMap<vk::DescriptorSetLayout, DescriptorShape>,
+}
+
+impl DescriptorTemplateCache {
+    pub fn len(&self) -> usize {
+        self.templates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.templates.is_empty()
+    }
+
+    pub fn layout_shape(&self, layout: vk::DescriptorSetLayout) -> Option<&DescriptorShape> {
+        self.layout_shapes.get(&layout)
+    }
+}
+
+// packs contents into template data. None if template can not write it (not presented binding, array size mismatch)
+pub fn pack_descriptor_template_data(
+    shape: &DescriptorShape,
+    contents: &[DescriptorContents],
+) -> Option<Vec<DescriptorTemplateData>> {
+    if contents.len() != shape.bindings.len() {
+        return None;
+    }
+
+    let mut data = Vec::with_capacity(shape.descriptor_count() as usize);
+    for ((_, _, count, _, _), contents) in shape.bindings.iter().zip(contents) {
+        match contents {
+            DescriptorContents::NotPresented => return None,
+            DescriptorContents::Images(images) => {
+                if images.len() != *count as usize {
+                    return None;
+                }
+                data.extend(images.iter().map(|&(image_view, image_layout, sampler)| {
+                    DescriptorTemplateData {
+                        image: vk::DescriptorImageInfo {
+                            sampler,
+                            image_view,
+                            image_layout,
+                        },
+                    }
+                }));
+            }
+            DescriptorContents::TexelBuffers(views) => {
+                if views.len() != *count as usize {
+                    return None;
+                }
+                data.extend(
+                    views
+                        .iter()
+                        .map(|&texel_buffer_view| DescriptorTemplateData { texel_buffer_view }),
+                );
+            }
+            DescriptorContents::Buffers(buffers) => {
+                if buffers.len() != *count as usize {
+                    return None;
+                }
+                data.extend(buffers.iter().map(|&(buffer, offset, range)| {
+                    DescriptorTemplateData {
+                        buffer: vk::DescriptorBufferInfo {
+                            buffer,
+                            offset,
+                            range,
+                        },
+                    }
+                }));
+            }
+        }
+    }
+    Some(data)
+}
+
+impl Renderer {
+    // called from create_descriptor_set_layout, so templates can be found by layout later
+    pub(crate) fn record_layout_shape(
+        &mut self,
+        layout: vk::DescriptorSetLayout,
+        infos: &[ShortDescriptorInfo],
+        flags: vk::DescriptorSetLayoutCreateFlags,
+    ) {
+        self.descriptor_templates
+            .layout_shapes
+            .insert(layout, DescriptorShape::new(infos, flags));
+    }
+
+    // layout handles might be reused by driver after destruction, so forget them when destroyed
+    // (set templates are keyed by shape and stay valid for other identical layouts)
+    #[cold]
+    #[optimize(size)]
+    pub fn forget_set_layout_templates(&mut self, set_layout: vk::DescriptorSetLayout) {
+        self.descriptor_templates.layout_shapes.remove(&set_layout);
+    }
+
+    // push templates are created for specific pipeline layout
+    #[cold]
+    #[optimize(size)]
+    pub fn forget_pipeline_layout_templates(&mut self, line_layout: vk::PipelineLayout) {
+        let device = &self.device;
+        self.descriptor_templates.templates.retain(|key, template| {
+            let stale = key.push.is_some_and(|(layout, _, _)| layout == line_layout);
+            if stale {
+                unsafe { device.destroy_descriptor_update_template(*template, None) };
+            }
+            !stale
+        });
+    }
+
+    // template for sets of set_layout, created on first use. None if layout shape can not be templated
+    // push is (pipeline layout, bind point, set index) for push descriptor templates
+    #[optimize(speed)]
+    pub fn get_descriptor_template(
+        &mut self,
+        set_layout: vk::DescriptorSetLayout,
+        push: Option<(vk::PipelineLayout, vk::PipelineBindPoint, u32)>,
+    ) -> Option<vk::DescriptorUpdateTemplate> {
+        let shape = self.descriptor_templates.layout_shapes.get(&set_layout)?;
+        if !shape.templatable() {
+            return None;
+        }
+        let key = TemplateKey {
+            shape: shape.clone(),
+            push,
+        };
+        if let Some(template) = self.descriptor_templates.templates.get(&key) {
+            return Some(*template);
+        }
+
+        let template = self.create_descriptor_template(set_layout, &key.shape, push);
+        self.descriptor_templates.templates.insert(key, template);
+        Some(template)
+    }
+
+    #[cold]
+    #[optimize(size)]
+    fn create_descriptor_template(
+        &self,
+        set_layout: vk::DescriptorSetLayout,
+        shape: &DescriptorShape,
+        push: Option<(vk::PipelineLayout, vk::PipelineBindPoint, u32)>,
+    ) -> vk::DescriptorUpdateTemplate {
+        let stride = size_of::<DescriptorTemplateData>();
+        let mut offset = 0;
+        let entries: Vec<vk::DescriptorUpdateTemplateEntry> = shape
+            .bindings
+            .iter()
+            .map(|&(binding, descriptor_type, count, _, _)| {
+                let entry = vk::DescriptorUpdateTemplateEntry {
+                    dst_binding: binding,
+                    dst_array_element: 0,
+                    descriptor_count: count,
+                    descriptor_type,
+                    offset,
+                    stride,
+                };
+                offset += count as usize * stride;
+                entry
+            })
+            .collect();
+
+        let (template_type, pipeline_layout, pipeline_bind_point, set) = match push {
+            Some((layout, bind_point, set)) => (
+                vk::DescriptorUpdateTemplateType::PUSH_DESCRIPTORS_KHR,
+                layout,
+                bind_point,
+                set,
+            ),
+            None => (
+                vk::DescriptorUpdateTemplateType::DESCRIPTOR_SET,
+                vk::PipelineLayout::null(),
+                vk::PipelineBindPoint::default(),
+                0,
+            ),
+        };
+
+        let create_info = vk::DescriptorUpdateTemplateCreateInfo {
+            descriptor_update_entry_count: entries.len() as u32,
+            p_descriptor_update_entries: entries.as_ptr(),
+            template_type,
+            descriptor_set_layout: set_layout,
+            pipeline_bind_point,
+            pipeline_layout,
+            set,
+            ..Default::default()
+        };
+        unsafe { self.device.create_descriptor_update_template(&create_info, None) }
+            .expect("Failed to create descriptor update template")
+    }
+
+    // typed vkUpdateDescriptorSetWithTemplate. data has to be packed for template's shape
+    #[optimize(speed)]
+    pub fn update_descriptor_set_with_template(
+        &self,
+        set: vk::DescriptorSet,
+        template: vk::DescriptorUpdateTemplate,
+        data: &[DescriptorTemplateData],
+    ) {
+        unsafe {
+            self.device.update_descriptor_set_with_template(
+                set,
+                template,
+                data.as_ptr() as *const std::ffi::c_void,
+            )
+        };
+    }
+
+    // template and packed data to rewrite whole set (allocated with acutally_setup_descriptor)
+    pub(crate) fn template_data_for_set(
+        &mut self,
+        set: vk::DescriptorSet,
+        contents: &[DescriptorContents],
+    ) -> Option<(vk::DescriptorUpdateTemplate, Vec<DescriptorTemplateData>)> {
+        let set_layout = *self.descriptor_set_layouts.get(&set)?;
+        let shape = self.descriptor_templates.layout_shapes.get(&set_layout)?;
+        let data = pack_descriptor_template_data(shape, contents)?;
+        let template = self.get_descriptor_template(set_layout, None)?;
+        Some((template, data))
+    }
+
+    #[cold]
+    #[optimize(size)]
+    pub fn destroy_descriptor_templates(&mut self) {
+        for (_, template) in self.descriptor_templates.templates.drain() {
+            unsafe { self.device.destroy_descriptor_update_template(template, None) };
+        }
+        self.descriptor_templates.layout_shapes.clear();
+    }
+}
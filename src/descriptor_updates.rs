@@ -0,0 +1,332 @@
+// rewriting already set up descriptor sets when resources they point to change (resize, texture swap)
+// last written contents of every set are remembered, so only changed bindings are written
+
+use crate::descriptors::{DescriptorInfo, RelativeDescriptorPos};
+use crate::{ring::Ring, Buffer, Renderer, MAX_FRAMES_IN_FLIGHT};
+use ash::{vk, Device};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DescriptorUpdateMode {
+    // all frames are written right now. Only valid when no frame in flight uses sets (e.g. after wait_idle)
+    Immediate,
+    // set of every frame is written in start_frame, after fence of that frame is signaled
+    // assumes pipe sets Ring moves in lockstep with frames (which is how they are bound)
+    Deferred,
+}
+
+// what single binding of single set points to. Raw handles, so it can outlive DescriptorInfo
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum DescriptorContents {
+    #[default]
+    NotPresented,
+    Images(Vec<(vk::ImageView, vk::ImageLayout, vk::Sampler)>),
+    Buffers(Vec<(vk::Buffer, vk::DeviceSize, vk::DeviceSize)>),
+    TexelBuffers(Vec<vk::BufferView>),
+}
+
+#[derive(Debug)]
+pub struct PendingDescriptorWrite {
+    pub set: vk::DescriptorSet,
+    pub frame_i: usize, // index in sets Ring, written when frame with this index starts
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub contents: DescriptorContents,
+}
+
+// contents every binding of set for frame_i should have
+pub fn resolve_descriptor_contents(
+    descriptions: &[DescriptorInfo],
+    frame_i: usize,
+    fif: usize,
+) -> Vec<DescriptorContents> {
+    let previous_frame_i = if frame_i == 0 { fif - 1 } else { frame_i - 1 };
+
+    descriptions
+        .iter()
+        .map(|desc| {
+            let descriptor_frame_id = match desc.relative_pos {
+                RelativeDescriptorPos::Current => frame_i,
+                RelativeDescriptorPos::Previous => previous_frame_i,
+                RelativeDescriptorPos::First => 0,
+                RelativeDescriptorPos::NotPresented => return DescriptorContents::NotPresented,
+            };
+
+            let texel = desc.descriptor_type == vk::DescriptorType::UNIFORM_TEXEL_BUFFER
+                || desc.descriptor_type == vk::DescriptorType::STORAGE_TEXEL_BUFFER;
+            // texel buffers are bound through view owned by buffer
+            let texel_view = |buffer: &Buffer| {
+                *buffer.views.get(desc.buffer_view).expect(
+                    "Texel buffer descriptor, but buffer has no such view (create_buffer_view)",
+                )
+            };
+
+            if desc.descriptor_type == vk::DescriptorType::SAMPLER {
+                // standalone sampler, no image
+                assert!(
+                    desc.image_sampler != vk::Sampler::null(),
+                    "SAMPLER binding without sampler"
+                );
+                DescriptorContents::Images(vec![(
+                    vk::ImageView::null(),
+                    vk::ImageLayout::UNDEFINED,
+                    desc.image_sampler,
+                )])
+            } else if texel && !desc.buffer_array.is_empty() {
+                DescriptorContents::TexelBuffers(
+                    desc.buffer_array
+                        .iter()
+                        .map(|buffers| texel_view(&buffers[descriptor_frame_id]))
+                        .collect(),
+                )
+            } else if texel {
+                let buffers = desc.buffers.expect("Texel buffer descriptor without buffers");
+                DescriptorContents::TexelBuffers(vec![texel_view(&buffers[descriptor_frame_id])])
+            } else if !desc.image_array.is_empty() {
+                DescriptorContents::Images(
+                    desc.image_array
+                        .iter()
+                        .map(|images| {
+                            (
+                                images[descriptor_frame_id].view,
+                                desc.image_layout,
+                                desc.image_sampler,
+                            )
+                        })
+                        .collect(),
+                )
+            } else if !desc.buffer_array.is_empty() {
+                DescriptorContents::Buffers(
+                    desc.buffer_array
+                        .iter()
+                        .map(|buffers| (buffers[descriptor_frame_id].buffer, 0, vk::WHOLE_SIZE))
+                        .collect(),
+                )
+            } else if let Some(images) = &desc.images {
+                assert!(images[descriptor_frame_id].view != vk::ImageView::null());
+                assert!(desc.buffers.is_none());
+                if desc.image_sampler != vk::Sampler::null()
+                    && desc.descriptor_type != vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+                {
+                    panic!("Descriptor has sampler but type is not for sampler");
+                }
+                DescriptorContents::Images(vec![(
+                    images[descriptor_frame_id].view,
+                    desc.image_layout,
+                    desc.image_sampler,
+                )])
+            } else if let Some(buffers) = &desc.buffers {
+                DescriptorContents::Buffers(vec![(
+                    buffers[descriptor_frame_id].buffer,
+                    0,
+                    vk::WHOLE_SIZE,
+                )])
+            } else if let Some(ranges) = &desc.buffer_ranges {
+                let range = ranges[descriptor_frame_id];
+                DescriptorContents::Buffers(vec![(range.buffer, range.offset, range.size)])
+            } else {
+                panic!("Unknown descriptor type");
+            }
+        })
+        .collect()
+}
+
+// (binding, type, contents) -> vkUpdateDescriptorSets. NotPresented bindings are skipped
+pub(crate) unsafe fn write_descriptor_contents(
+    device: &Device,
+    set: vk::DescriptorSet,
+    bindings: &[(u32, vk::DescriptorType, &DescriptorContents)],
+) {
+    with_descriptor_writes(set, bindings, |writes| {
+        device.update_descriptor_sets(writes, &[]);
+    });
+}
+
+// builds vk::WriteDescriptorSet's (pointing into temporary info Vecs) and passes them to f
+// shared by set updates and push descriptors (where set is null)
+pub(crate) fn with_descriptor_writes(
+    set: vk::DescriptorSet,
+    bindings: &[(u32, vk::DescriptorType, &DescriptorContents)],
+    f: impl FnOnce(&[vk::WriteDescriptorSet]),
+) {
+    // one Vec per binding, so writes can point into them
+    let mut image_infos = vec![vec![]; bindings.len()];
+    let mut buffer_infos = vec![vec![]; bindings.len()];
+    let mut writes = Vec::with_capacity(bindings.len());
+
+    for (i, (binding, descriptor_type, contents)) in bindings.iter().enumerate() {
+        let mut write = vk::WriteDescriptorSet {
+            dst_set: set,
+            dst_binding: *binding,
+            dst_array_element: 0,
+            descriptor_type: *descriptor_type,
+            ..Default::default()
+        };
+        match contents {
+            DescriptorContents::NotPresented => continue,
+            DescriptorContents::Images(images) => {
+                image_infos[i] = images
+                    .iter()
+                    .map(
+                        |&(image_view, image_layout, sampler)| vk::DescriptorImageInfo {
+                            sampler,
+                            image_view,
+                            image_layout,
+                        },
+                    )
+                    .collect();
+                write.descriptor_count = image_infos[i].len() as u32;
+                write.p_image_info = image_infos[i].as_ptr();
+            }
+            DescriptorContents::TexelBuffers(views) => {
+                write.descriptor_count = views.len() as u32;
+                write.p_texel_buffer_view = views.as_ptr();
+            }
+            DescriptorContents::Buffers(buffers) => {
+                buffer_infos[i] = buffers
+                    .iter()
+                    .map(|&(buffer, offset, range)| vk::DescriptorBufferInfo {
+                        buffer,
+                        offset,
+                        range,
+                    })
+                    .collect();
+                write.descriptor_count = buffer_infos[i].len() as u32;
+                write.p_buffer_info = buffer_infos[i].as_ptr();
+            }
+        }
+        writes.push(write);
+    }
+
+    if !writes.is_empty() {
+        f(&writes);
+    }
+}
+
+impl Renderer {
+    // rewrites bindings of already set up sets whose resources changed (compared to what was written last time)
+    // descriptions are same as in setup, just with new resources. name is pipe name for validation errors
+    #[cold]
+    #[optimize(size)]
+    pub fn update_descriptors(
+        &mut self,
+        descriptor_sets: &Ring<vk::DescriptorSet>,
+        descriptions: &[DescriptorInfo],
+        mode: DescriptorUpdateMode,
+        name: Option<&str>,
+    ) {
+        self.validate_descriptors(descriptions, name);
+
+        for frame_i in 0..descriptor_sets.len() {
+            let set = descriptor_sets[frame_i];
+            assert!(
+                set != vk::DescriptorSet::null(),
+                "Descriptor set is not set up"
+            );
+
+            let new_contents =
+                resolve_descriptor_contents(descriptions, frame_i, self.settings.fif);
+            let old_contents = self.descriptor_contents.get(&set);
+            // indices of changed descriptions (not binding numbers)
+            let changed: Vec<usize> = new_contents
+                .iter()
+                .enumerate()
+                .filter(|(i, contents)| {
+                    **contents != DescriptorContents::NotPresented
+                        && old_contents.and_then(|old| old.get(*i)) != Some(*contents)
+                })
+                .map(|(i, _)| i)
+                .collect();
+            let changed_bindings: Vec<u32> =
+                changed.iter().map(|&i| descriptions[i].binding_number(i)).collect();
+
+            match mode {
+                DescriptorUpdateMode::Immediate => {
+                    // older deferred writes of same bindings would overwrite this later
+                    self.pending_descriptor_writes.retain(|pending| {
+                        pending.set != set || !changed_bindings.contains(&pending.binding)
+                    });
+                    if !changed.is_empty() {
+                        self.write_changed_bindings(set, descriptions, &new_contents, &changed);
+                    }
+                }
+                DescriptorUpdateMode::Deferred => {
+                    for (&i, &binding) in changed.iter().zip(&changed_bindings) {
+                        self.pending_descriptor_writes.push(PendingDescriptorWrite {
+                            set,
+                            frame_i,
+                            binding,
+                            descriptor_type: descriptions[i].descriptor_type,
+                            contents: new_contents[i].clone(),
+                        });
+                    }
+                }
+            }
+
+            // remember target state, so next update compares against it (even if write is still pending)
+            self.descriptor_contents.insert(set, new_contents);
+        }
+    }
+
+    // whole set through cached template if possible (unchanged bindings are rewritten with same values),
+    // otherwise only changed bindings with regular writes
+    fn write_changed_bindings(
+        &mut self,
+        set: vk::DescriptorSet,
+        descriptions: &[DescriptorInfo],
+        new_contents: &[DescriptorContents],
+        changed: &[usize],
+    ) {
+        if let Some((template, data)) = self.template_data_for_set(set, new_contents) {
+            self.update_descriptor_set_with_template(set, template, &data);
+            return;
+        }
+
+        let bindings: Vec<(u32, vk::DescriptorType, &DescriptorContents)> = changed
+            .iter()
+            .map(|&i| {
+                (
+                    descriptions[i].binding_number(i),
+                    descriptions[i].descriptor_type,
+                    &new_contents[i],
+                )
+            })
+            .collect();
+        unsafe { write_descriptor_contents(&self.device, set, &bindings) };
+    }
+
+    // called in start_frame after fence wait - sets of this frame index are not used by GPU anymore
+    #[optimize(speed)]
+    pub fn apply_pending_descriptor_writes(&mut self) {
+        if self.pending_descriptor_writes.is_empty() {
+            return;
+        }
+        let frame_i = self.vulkan_data.in_flight_fences.index;
+        assert!(frame_i < MAX_FRAMES_IN_FLIGHT);
+
+        let (ready, pending): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.pending_descriptor_writes)
+                .into_iter()
+                .partition(|pending| pending.frame_i == frame_i);
+        self.pending_descriptor_writes = pending;
+
+        // in order, later writes of same binding win
+        for write in &ready {
+            unsafe {
+                write_descriptor_contents(
+                    &self.device,
+                    write.set,
+                    &[(write.binding, write.descriptor_type, &write.contents)],
+                )
+            };
+        }
+    }
+
+    // called when sets are freed, so stale handles do not get written into
+    pub(crate) fn forget_descriptor_sets(&mut self, sets: &[vk::DescriptorSet]) {
+        for set in sets {
+            self.descriptor_contents.remove(set);
+            self.descriptor_set_layouts.remove(set);
+        }
+        self.pending_descriptor_writes.retain(|pending| !sets.contains(&pending.set));
+    }
+}
@@ -0,0 +1,197 @@
+// checks that resources bound to descriptors can actually be used that way
+// (usage flags, buffer ranges vs device limits). Runs on setup / update, all problems are reported at once
+
+use crate::descriptors::{binding_numbers, DescriptorInfo, RelativeDescriptorPos};
+use crate::{Buffer, Image, Renderer};
+use ash::vk;
+
+// usage image needs for descriptor type, None if type is not for images
+fn required_image_usage(descriptor_type: vk::DescriptorType) -> Option<vk::ImageUsageFlags> {
+    match descriptor_type {
+        vk::DescriptorType::STORAGE_IMAGE => Some(vk::ImageUsageFlags::STORAGE),
+        vk::DescriptorType::SAMPLED_IMAGE | vk::DescriptorType::COMBINED_IMAGE_SAMPLER => {
+            Some(vk::ImageUsageFlags::SAMPLED)
+        }
+        vk::DescriptorType::INPUT_ATTACHMENT => Some(vk::ImageUsageFlags::INPUT_ATTACHMENT),
+        _ => None,
+    }
+}
+
+// usage buffer needs for descriptor type, None if type is not for buffers
+fn required_buffer_usage(descriptor_type: vk::DescriptorType) -> Option<vk::BufferUsageFlags> {
+    match descriptor_type {
+        vk::DescriptorType::UNIFORM_BUFFER | vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC => {
+            Some(vk::BufferUsageFlags::UNIFORM_BUFFER)
+        }
+        vk::DescriptorType::STORAGE_BUFFER | vk::DescriptorType::STORAGE_BUFFER_DYNAMIC => {
+            Some(vk::BufferUsageFlags::STORAGE_BUFFER)
+        }
+        vk::DescriptorType::UNIFORM_TEXEL_BUFFER => {
+            Some(vk::BufferUsageFlags::UNIFORM_TEXEL_BUFFER)
+        }
+        vk::DescriptorType::STORAGE_TEXEL_BUFFER => {
+            Some(vk::BufferUsageFlags::STORAGE_TEXEL_BUFFER)
+        }
+        _ => None,
+    }
+}
+
+impl Renderer {
+    // panics with every incompatible binding listed. name is pipe (debug) name for the message
+    #[cold]
+    #[optimize(size)]
+    pub fn validate_descriptors(&self, descriptions: &[DescriptorInfo], name: Option<&str>) {
+        let limits = unsafe {
+            self.instance.get_physical_device_properties(self.vulkan_data.physical_device)
+        }
+        .limits;
+
+        let numbers = binding_numbers(
+            descriptions.iter().enumerate().map(|(i, desc)| desc.binding_number(i)),
+        );
+        let highest = numbers.iter().copied().max();
+
+        let mut errors = vec![];
+        for (i, desc) in descriptions.iter().enumerate() {
+            // messages name binding number, not position in descriptions
+            let binding = numbers[i];
+            // explicit count is binding size, arrays can only fill part of it
+            let array_len = desc.image_array.len().max(desc.buffer_array.len());
+            if desc.count != 0 && array_len > desc.count as usize {
+                errors.push(format!(
+                    "binding {binding}: array of {array_len} does not fit into count {}",
+                    desc.count
+                ));
+            }
+            if desc
+                .binding_flags
+                .contains(vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT)
+                && Some(binding) != highest
+            {
+                errors.push(format!(
+                    "binding {binding}: VARIABLE_DESCRIPTOR_COUNT is only allowed on highest binding number"
+                ));
+            }
+
+            if let RelativeDescriptorPos::NotPresented = desc.relative_pos {
+                continue;
+            }
+            let descriptor_type = desc.descriptor_type;
+
+            // every frame's resource is checked, not just current one
+            let mut images: Vec<&Image> = vec![];
+            for ring in desc.images.iter().chain(desc.image_array) {
+                images.extend(ring.iter());
+            }
+            let mut buffers: Vec<&Buffer> = vec![];
+            for ring in desc.buffers.iter().chain(desc.buffer_array) {
+                buffers.extend(ring.iter());
+            }
+            // ranges only know vk::Buffer, so only size can be checked
+            let ranges: Vec<vk::DeviceSize> = desc
+                .buffer_ranges
+                .iter()
+                .flat_map(|ring| ring.iter().map(|range| range.size))
+                .collect();
+
+            match (
+                required_image_usage(descriptor_type),
+                required_buffer_usage(descriptor_type),
+            ) {
+                (Some(usage), _) => {
+                    if !buffers.is_empty() || !ranges.is_empty() {
+                        errors.push(format!(
+                            "binding {binding}: buffer bound to image descriptor {descriptor_type:?}"
+                        ));
+                    }
+                    for image in &images {
+                        if !image.usage.contains(usage) {
+                            errors.push(format!(
+                                "binding {binding}: {descriptor_type:?} requires image usage {usage:?}, image has {:?}",
+                                image.usage
+                            ));
+                        }
+                    }
+                    if descriptor_type == vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+                        && desc.image_sampler == vk::Sampler::null()
+                    {
+                        errors.push(format!(
+                            "binding {binding}: COMBINED_IMAGE_SAMPLER without sampler"
+                        ));
+                    }
+                }
+                (None, Some(usage)) => {
+                    if !images.is_empty() {
+                        errors.push(format!(
+                            "binding {binding}: image bound to buffer descriptor {descriptor_type:?}"
+                        ));
+                    }
+
+                    let max_range = match usage {
+                        vk::BufferUsageFlags::UNIFORM_BUFFER => {
+                            Some(limits.max_uniform_buffer_range as vk::DeviceSize)
+                        }
+                        vk::BufferUsageFlags::STORAGE_BUFFER => {
+                            Some(limits.max_storage_buffer_range as vk::DeviceSize)
+                        }
+                        _ => None,
+                    };
+
+                    let texel = usage == vk::BufferUsageFlags::UNIFORM_TEXEL_BUFFER
+                        || usage == vk::BufferUsageFlags::STORAGE_TEXEL_BUFFER;
+                    for buffer in &buffers {
+                        if !buffer.usage.contains(usage) {
+                            errors.push(format!(
+                                "binding {binding}: {descriptor_type:?} requires buffer usage {usage:?}, buffer has {:?}",
+                                buffer.usage
+                            ));
+                        }
+                        if texel && buffer.views.len() <= desc.buffer_view {
+                            errors.push(format!(
+                                "binding {binding}: {descriptor_type:?} needs buffer view {}, buffer has {} (create_buffer_view)",
+                                desc.buffer_view,
+                                buffer.views.len()
+                            ));
+                        }
+                    }
+                    if texel && !ranges.is_empty() {
+                        errors.push(format!(
+                            "binding {binding}: buffer ranges can not be bound as {descriptor_type:?}, use buffer views"
+                        ));
+                    }
+                    // whole buffers are bound with WHOLE_SIZE, which is buffer size
+                    let sizes = buffers.iter().map(|buffer| buffer.size).chain(ranges);
+                    if let Some(max_range) = max_range {
+                        for size in sizes {
+                            if size > max_range {
+                                errors.push(format!(
+                                    "binding {binding}: {descriptor_type:?} range {size} exceeds device limit {max_range}"
+                                ));
+                            }
+                        }
+                    }
+                }
+                (None, None) => {
+                    if descriptor_type == vk::DescriptorType::SAMPLER {
+                        if desc.image_sampler == vk::Sampler::null() {
+                            errors.push(format!("binding {binding}: SAMPLER without sampler"));
+                        }
+                        if !images.is_empty() || !buffers.is_empty() || !ranges.is_empty() {
+                            errors.push(format!(
+                                "binding {binding}: SAMPLER binding can not have images or buffers"
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            panic!(
+                "Incompatible descriptors in {}:\n  {}",
+                name.unwrap_or("<unnamed pipe>"),
+                errors.join("\n  ")
+            );
+        }
+    }
+}
@@ -1,7 +1,14 @@
 use ash::{vk, Device};
 
+use crate::arena::BufferRange;
+use crate::descriptor_pool::DescriptorPoolAllocator;
+use crate::descriptor_templates::pack_descriptor_template_data;
+use crate::descriptor_updates::{
+    resolve_descriptor_contents, with_descriptor_writes, write_descriptor_contents,
+    DescriptorContents,
+};
 use crate::{
-    ring::Ring, set_debug_names, Buffer, DescriptorCounter, Image, LumalSettings, RasterPipe,
+    ring::Ring, set_debug_names, Buffer, DescriptorCounter, Image, LumalSettings, Pipe, RasterPipe,
     MAX_FRAMES_IN_FLIGHT,
 };
 use crate::{set_debug_name, Renderer};
@@ -119,12 +126,28 @@ pub struct AttrFormOffs {
 #[derive(Debug, Default)]
 pub struct DescriptorInfo<'a> {
     pub descriptor_type: vk::DescriptorType,
+    // explicit binding number (for shaders with gaps / shared bindings). None is index in descriptions
+    pub binding: Option<u32>,
+    // explicit descriptor count (e.g. bigger partially bound array). 0 is array length or 1
+    pub count: u32,
     pub relative_pos: RelativeDescriptorPos,
     pub buffers: Option<&'a Ring<Buffer>>,
+    // same as buffers, but points to sub-ranges (e.g. from BufferArena) instead of whole buffers
+    pub buffer_ranges: Option<&'a Ring<BufferRange>>,
     pub images: Option<&'a Ring<Image>>,
+    // descriptor arrays - element j of binding is image_array[j] / buffer_array[j] (per frame as usual)
+    // used instead of images / buffers when not empty
+    pub image_array: &'a [&'a Ring<Image>],
+    pub buffer_array: &'a [&'a Ring<Buffer>],
+    // for COMBINED_IMAGE_SAMPLER and standalone SAMPLER bindings (which need no images)
     pub image_sampler: vk::Sampler,
     pub image_layout: vk::ImageLayout, // Image layout for use (not current)
+    // index into Buffer::views for texel buffer bindings (see create_buffer_view)
+    pub buffer_view: usize,
     pub specified_stages: vk::ShaderStageFlags,
+    // PARTIALLY_BOUND / VARIABLE_DESCRIPTOR_COUNT (last binding only) for arrays
+    // UPDATE_AFTER_BIND is not supported here, BindlessTable has its own pool for that
+    pub binding_flags: vk::DescriptorBindingFlags,
 }
 
 impl<'a> DescriptorInfo<'a> {
@@ -139,22 +162,103 @@ impl<'a> DescriptorInfo<'a> {
     ) -> Self {
         Self {
             descriptor_type,
+            binding: None,
+            count: 0,
             relative_pos,
             buffers,
+            buffer_ranges: None,
             images,
+            image_array: &[],
+            buffer_array: &[],
+            buffer_view: 0,
             image_sampler,
             image_layout,
             specified_stages: stages,
+            binding_flags: vk::DescriptorBindingFlags::empty(),
+        }
+    }
+
+    // number of descriptors in binding (explicit count, array length or 1)
+    pub fn descriptor_count(&self) -> u32 {
+        if self.count != 0 {
+            return self.count;
+        }
+        self.image_array.len().max(self.buffer_array.len()).max(1) as u32
+    }
+
+    // binding number of i-th description
+    pub fn binding_number(&self, i: usize) -> u32 {
+        self.binding.unwrap_or(i as u32)
+    }
+}
+
+// binding numbers of descriptions, panics on duplicates
+pub fn binding_numbers(bindings: impl Iterator<Item = u32>) -> Vec<u32> {
+    let numbers: Vec<u32> = bindings.collect();
+    for (i, number) in numbers.iter().enumerate() {
+        if let Some(other) = numbers[..i].iter().position(|n| n == number) {
+            panic!("Descriptor binding {number} is used twice (descriptions {other} and {i})");
         }
     }
+    numbers
 }
 
 pub struct ShortDescriptorInfo {
     pub descriptor_type: vk::DescriptorType,
+    pub binding: Option<u32>, // explicit binding number, None is index
     pub stages: vk::ShaderStageFlags,
+    pub count: u32, // array length, 1 for plain descriptor
+    pub binding_flags: vk::DescriptorBindingFlags,
 }
 
 impl Renderer {
+    // adds descriptors of layout to DescriptorCounter, which sizes first descriptor pool
+    #[cold]
+    #[optimize(size)]
+    pub fn count_descriptors(
+        &mut self,
+        descriptor_infos: &[ShortDescriptorInfo],
+        flags: vk::DescriptorSetLayoutCreateFlags,
+    ) {
+        // push descriptors live in command buffer, not in pool - nothing to count
+        if flags.contains(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR) {
+            return;
+        }
+        for info in descriptor_infos {
+            macro_rules! make_descriptor_type {
+                ($name:ident) => {
+                    self.descriptor_counter.$name += info.count
+                };
+            }
+            match info.descriptor_type {
+                vk::DescriptorType::SAMPLER => make_descriptor_type!(SAMPLER),
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER => {
+                    make_descriptor_type!(COMBINED_IMAGE_SAMPLER)
+                }
+                vk::DescriptorType::SAMPLED_IMAGE => make_descriptor_type!(SAMPLED_IMAGE),
+                vk::DescriptorType::STORAGE_IMAGE => make_descriptor_type!(STORAGE_IMAGE),
+                vk::DescriptorType::UNIFORM_TEXEL_BUFFER => {
+                    make_descriptor_type!(UNIFORM_TEXEL_BUFFER)
+                }
+                vk::DescriptorType::STORAGE_TEXEL_BUFFER => {
+                    make_descriptor_type!(STORAGE_TEXEL_BUFFER)
+                }
+                vk::DescriptorType::UNIFORM_BUFFER => make_descriptor_type!(UNIFORM_BUFFER),
+                vk::DescriptorType::STORAGE_BUFFER => make_descriptor_type!(STORAGE_BUFFER),
+                vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC => {
+                    make_descriptor_type!(UNIFORM_BUFFER_DYNAMIC)
+                }
+                vk::DescriptorType::STORAGE_BUFFER_DYNAMIC => {
+                    make_descriptor_type!(STORAGE_BUFFER_DYNAMIC)
+                }
+                vk::DescriptorType::INPUT_ATTACHMENT => make_descriptor_type!(INPUT_ATTACHMENT),
+                _ => {
+                    panic!("Unknown descriptor type");
+                }
+            }
+        }
+    }
+
     /// immediately creates vulkan descriptor set layout
     #[cold]
     #[optimize(size)]
@@ -165,58 +269,74 @@ impl Renderer {
         flags: vk::DescriptorSetLayoutCreateFlags,
         #[cfg(feature = "debug_validation_names")] debug_name: Option<&str>,
     ) {
+        self.count_descriptors(descriptor_infos, flags);
+
+        let push = flags.contains(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR);
+        let numbers = binding_numbers(
+            descriptor_infos
+                .iter()
+                .enumerate()
+                .map(|(i, info)| info.binding.unwrap_or(i as u32)),
+        );
+        let highest = numbers.iter().copied().max();
+        for (i, info) in descriptor_infos.iter().enumerate() {
+            assert!(
+                !info
+                    .binding_flags
+                    .contains(vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT)
+                    || Some(numbers[i]) == highest,
+                "VARIABLE_DESCRIPTOR_COUNT binding {} is not the highest binding",
+                numbers[i]
+            );
+        }
         let bindings: Vec<vk::DescriptorSetLayoutBinding> = descriptor_infos
             .iter()
             .enumerate()
             .map(|(i, info)| {
-                macro_rules! make_descriptor_type {
-                    ($name:ident) => {
-                        self.descriptor_counter.$name += 1
-                    };
-                }
-                match info.descriptor_type {
-                    vk::DescriptorType::SAMPLER => make_descriptor_type!(SAMPLER),
-                    vk::DescriptorType::COMBINED_IMAGE_SAMPLER => {
-                        make_descriptor_type!(COMBINED_IMAGE_SAMPLER)
-                    }
-                    vk::DescriptorType::SAMPLED_IMAGE => make_descriptor_type!(SAMPLED_IMAGE),
-                    vk::DescriptorType::STORAGE_IMAGE => make_descriptor_type!(STORAGE_IMAGE),
-                    vk::DescriptorType::UNIFORM_TEXEL_BUFFER => {
-                        make_descriptor_type!(UNIFORM_TEXEL_BUFFER)
-                    }
-                    vk::DescriptorType::STORAGE_TEXEL_BUFFER => {
-                        make_descriptor_type!(STORAGE_TEXEL_BUFFER)
-                    }
-                    vk::DescriptorType::UNIFORM_BUFFER => make_descriptor_type!(UNIFORM_BUFFER),
-                    vk::DescriptorType::STORAGE_BUFFER => make_descriptor_type!(STORAGE_BUFFER),
-                    vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC => {
-                        make_descriptor_type!(UNIFORM_BUFFER_DYNAMIC)
-                    }
-                    vk::DescriptorType::STORAGE_BUFFER_DYNAMIC => {
-                        make_descriptor_type!(STORAGE_BUFFER_DYNAMIC)
-                    }
-                    vk::DescriptorType::INPUT_ATTACHMENT => make_descriptor_type!(INPUT_ATTACHMENT),
-                    _ => {
-                        panic!("Unknown descriptor type");
-                    }
+                // generic pools are not created with UPDATE_AFTER_BIND_POOL
+                assert!(
+                    !info.binding_flags.contains(vk::DescriptorBindingFlags::UPDATE_AFTER_BIND),
+                    "UPDATE_AFTER_BIND is not supported for regular descriptors, use BindlessTable"
+                );
+                if push {
+                    assert!(
+                        info.descriptor_type != vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC
+                            && info.descriptor_type != vk::DescriptorType::STORAGE_BUFFER_DYNAMIC,
+                        "Dynamic buffers can not be pushed"
+                    );
+                    assert!(
+                        info.binding_flags.is_empty(),
+                        "Push descriptors do not support binding flags"
+                    );
                 }
-
                 vk::DescriptorSetLayoutBinding {
-                    binding: i as u32,
+                    binding: numbers[i],
                     descriptor_type: info.descriptor_type,
-                    descriptor_count: 1,
+                    descriptor_count: info.count,
                     stage_flags: info.stages,
                     ..Default::default()
                 }
             })
             .collect();
 
-        let layout_info = vk::DescriptorSetLayoutCreateInfo {
+        // only chained when someone actually uses them
+        let binding_flags: Vec<vk::DescriptorBindingFlags> =
+            descriptor_infos.iter().map(|info| info.binding_flags).collect();
+        let binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo {
+            binding_count: binding_flags.len() as u32,
+            p_binding_flags: binding_flags.as_ptr(),
+            ..Default::default()
+        };
+
+        let mut layout_info = vk::DescriptorSetLayoutCreateInfo {
             flags,
             binding_count: bindings.len() as u32,
             p_bindings: bindings.as_ptr(),
             ..Default::default()
         };
+        if binding_flags.iter().any(|f| !f.is_empty()) {
+            layout_info.p_next = &binding_flags_info as *const _ as *const std::ffi::c_void;
+        }
 
         // actually create layout and write it to ref
         *layout = unsafe {
@@ -224,6 +344,7 @@ impl Renderer {
                 .create_descriptor_set_layout(&layout_info, None)
                 .expect("Failed to create descriptor set layout")
         };
+        self.record_layout_shape(*layout, descriptor_infos, flags);
 
         #[cfg(feature = "debug_validation_names")]
         set_debug_names!(self, debug_name, (layout, " Layout"));
@@ -231,7 +352,7 @@ impl Renderer {
 
     #[cold]
     #[optimize(size)]
-    pub unsafe fn create_descriptor_pool(&self) -> vk::DescriptorPool {
+    pub unsafe fn create_descriptor_pool(&mut self) {
         let mut pool_sizes = Vec::new();
 
         macro_rules! make_descriptor_type {
@@ -256,15 +377,15 @@ impl Renderer {
         make_descriptor_type!(STORAGE_BUFFER_DYNAMIC);
         make_descriptor_type!(INPUT_ATTACHMENT);
 
-        let pool_info = vk::DescriptorPoolCreateInfo {
-            pool_size_count: pool_sizes.len() as u32,
-            p_pool_sizes: pool_sizes.as_ptr(),
-            max_sets: self.descriptor_sets_count * self.settings.fif as u32,
-            flags: vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
-            ..Default::default()
-        };
-
-        self.device.create_descriptor_pool(&pool_info, None).unwrap()
+        // nothing announced - first pool will be generic one, created on first allocation
+        if pool_sizes.is_empty() {
+            return;
+        }
+        self.vulkan_data.descriptor_pools.add_pool(
+            &self.device,
+            &pool_sizes,
+            self.descriptor_sets_count * self.settings.fif as u32,
+        );
     }
 
     #[cold]
@@ -312,33 +433,36 @@ impl Renderer {
                 .iter()
                 .map(|desc| ShortDescriptorInfo {
                     descriptor_type: desc.descriptor_type,
+                    binding: desc.binding,
                     stages: if desc.specified_stages.is_empty() {
                         default_stages
                     } else {
                         desc.specified_stages
                     },
+                    count: desc.descriptor_count(),
+                    binding_flags: desc.binding_flags,
                 })
                 .collect();
-            unsafe {
-                // actually create layout and write it to ptr
-                self.create_descriptor_set_layout(
-                    &descriptor_infos,
-                    dset_layout,
-                    create_flags,
-                    #[cfg(feature = "debug_validation_names")]
-                    debug_name,
-                );
-            }
+            // shared with every other pipe with same bindings
+            *dset_layout = self.acquire_descriptor_set_layout(
+                &descriptor_infos,
+                create_flags,
+                #[cfg(feature = "debug_validation_names")]
+                debug_name,
+            );
         }
 
-        self.descriptor_sets_count += (MAX_FRAMES_IN_FLIGHT as u32); // cuase dset per fif
+        // push descriptor layouts have no sets, see cmd_push_descriptors
+        if !create_flags.contains(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR) {
+            self.descriptor_sets_count += (MAX_FRAMES_IN_FLIGHT as u32); // cuase dset per fif
+        }
     }
 
     // anounce is just a request, this is an actual logic
     #[cold]
     #[optimize(size)]
     pub unsafe fn actually_setup_descriptor_impl(
-        descriptor_pool: &vk::DescriptorPool,
+        descriptor_pools: &mut DescriptorPoolAllocator,
         settings: &LumalSettings,
         device: &Device,
         dset_layout: &vk::DescriptorSetLayout,
@@ -349,89 +473,69 @@ impl Renderer {
     ) {
         *descriptor_sets = Ring::new(MAX_FRAMES_IN_FLIGHT);
         let dset_layouts = [*dset_layout; MAX_FRAMES_IN_FLIGHT];
-        for frame_i in 0..MAX_FRAMES_IN_FLIGHT {
-            descriptor_sets[frame_i] = device
-                .allocate_descriptor_sets(&vk::DescriptorSetAllocateInfo {
-                    descriptor_pool: *descriptor_pool,
-                    descriptor_set_count: MAX_FRAMES_IN_FLIGHT as u32,
-                    p_set_layouts: dset_layouts.as_ptr(),
-                    ..Default::default()
-                })
-                .unwrap()[0];
-        }
-        assert!(descriptor_sets.len() == MAX_FRAMES_IN_FLIGHT);
-        for frame_i in 0..descriptor_sets.len() {
-            let previous_frame_i = if frame_i == 0 {
-                settings.fif - 1
-            } else {
-                frame_i - 1
-            };
 
-            let mut image_infos = vec![vk::DescriptorImageInfo::default(); descriptions.len()];
-            let mut buffer_infos = vec![vk::DescriptorBufferInfo::default(); descriptions.len()];
-            let mut writes = vec![vk::WriteDescriptorSet::default(); descriptions.len()];
-
-            for (i, desc) in descriptions.iter().enumerate() {
-                writes[i] = vk::WriteDescriptorSet {
-                    s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
-                    dst_set: descriptor_sets[frame_i],
-                    dst_binding: i as u32,
-                    dst_array_element: 0,
-                    descriptor_count: 1,
-                    descriptor_type: desc.descriptor_type,
-                    ..Default::default()
-                };
-
-                let descriptor_frame_id = match desc.relative_pos {
-                    RelativeDescriptorPos::Current => frame_i,
-                    RelativeDescriptorPos::Previous => previous_frame_i,
-                    RelativeDescriptorPos::First => 0,
-                    RelativeDescriptorPos::NotPresented => {
-                        writes[i].descriptor_count = 0;
-                        continue;
-                    }
-                };
+        // variable sized array (highest binding, checked in create_descriptor_set_layout)
+        // is allocated with exactly as many as we write
+        let variable_count = descriptions
+            .iter()
+            .find(|desc| {
+                desc.binding_flags
+                    .contains(vk::DescriptorBindingFlags::VARIABLE_DESCRIPTOR_COUNT)
+            })
+            .map(|desc| desc.descriptor_count());
+        let variable_counts = [variable_count.unwrap_or(0); MAX_FRAMES_IN_FLIGHT];
+        let variable_count_info = vk::DescriptorSetVariableDescriptorCountAllocateInfo {
+            descriptor_set_count: MAX_FRAMES_IN_FLIGHT as u32,
+            p_descriptor_counts: variable_counts.as_ptr(),
+            ..Default::default()
+        };
 
-                if let Some(images) = &desc.images {
-                    assert!(images[descriptor_frame_id].view != vk::ImageView::null());
-                    image_infos[i] = vk::DescriptorImageInfo {
-                        image_view: images[descriptor_frame_id].view,
-                        image_layout: desc.image_layout,
-                        sampler: desc.image_sampler,
-                    };
-                    writes[i].p_image_info = &image_infos[i];
-
-                    assert!(desc.buffers.is_none());
-                    if desc.image_sampler != vk::Sampler::null()
-                        && desc.descriptor_type != vk::DescriptorType::COMBINED_IMAGE_SAMPLER
-                    {
-                        panic!("Descriptor has sampler but type is not for sampler");
-                    }
-                } else if let Some(buffers) = &desc.buffers {
-                    buffer_infos[i] = vk::DescriptorBufferInfo {
-                        buffer: buffers[descriptor_frame_id].buffer,
-                        offset: 0,
-                        range: vk::WHOLE_SIZE as u64,
-                    };
-                    writes[i].p_buffer_info = &buffer_infos[i];
-                } else {
-                    panic!("Unknown descriptor type");
-                }
-            }
+        // what all sets need, in case allocator has to create new pool for them
+        let required: Vec<vk::DescriptorPoolSize> = descriptions
+            .iter()
+            .map(|desc| vk::DescriptorPoolSize {
+                ty: desc.descriptor_type,
+                descriptor_count: desc.descriptor_count() * MAX_FRAMES_IN_FLIGHT as u32,
+            })
+            .collect();
+        let p_next = if variable_count.is_some() {
+            &variable_count_info as *const _ as *const std::ffi::c_void
+        } else {
+            std::ptr::null()
+        };
 
-            device.update_descriptor_sets(&writes, &[] as &[vk::CopyDescriptorSet]);
+        // one set per frame, all allocated at once
+        let sets = descriptor_pools.allocate(device, &dset_layouts, &required, p_next);
+        for (frame_i, set) in sets.into_iter().enumerate() {
+            descriptor_sets[frame_i] = set;
+        }
+        assert!(descriptor_sets.len() == MAX_FRAMES_IN_FLIGHT);
+        for frame_i in 0..descriptor_sets.len() {
+            let contents = resolve_descriptor_contents(descriptions, frame_i, settings.fif);
+            let bindings: Vec<(u32, vk::DescriptorType, &DescriptorContents)> = descriptions
+                .iter()
+                .zip(&contents)
+                .enumerate()
+                .map(|(i, (desc, contents))| {
+                    (desc.binding_number(i), desc.descriptor_type, contents)
+                })
+                .collect();
+            write_descriptor_contents(device, descriptor_sets[frame_i], &bindings);
         }
     }
 
     #[cold]
     #[optimize(size)]
     pub fn flush_descriptor_setup(&mut self) {
-        // (actually) create Vulkan descriptor pool
-        if self.vulkan_data.descriptor_pool == vk::DescriptorPool::null() {
-            self.vulkan_data.descriptor_pool = unsafe { self.create_descriptor_pool() };
+        // (actually) create first Vulkan descriptor pool, sized from everything announced so far
+        // pipes announced after this get their sets from additional pools
+        if self.vulkan_data.descriptor_pools.pools.is_empty() {
+            unsafe { self.create_descriptor_pool() };
         }
     }
 
+    // validation errors only name the pipe with debug_validation_names,
+    // use acutally_setup_named_descriptor to get the name in every build
     #[cold]
     #[optimize(size)]
     pub fn acutally_setup_descriptor(
@@ -443,10 +547,45 @@ impl Renderer {
         create_flags: vk::DescriptorSetLayoutCreateFlags,
         #[cfg(feature = "debug_validation_names")] debug_name: Option<&str>,
     ) {
+        #[cfg(feature = "debug_validation_names")]
+        let name = debug_name;
+        #[cfg(not(feature = "debug_validation_names"))]
+        let name = None;
+        self.acutally_setup_named_descriptor(
+            dset_layout,
+            descriptor_sets,
+            descriptions,
+            default_stages,
+            create_flags,
+            name,
+        );
+    }
+
+    // same as acutally_setup_descriptor, name is not feature gated: validation errors name the pipe
+    // in every build (and it doubles as debug name with debug_validation_names)
+    #[cold]
+    #[optimize(size)]
+    pub fn acutally_setup_named_descriptor(
+        &mut self,
+        dset_layout: &mut vk::DescriptorSetLayout,
+        descriptor_sets: &mut Ring<vk::DescriptorSet>, // Ring to setup into
+        descriptions: &[DescriptorInfo],
+        default_stages: vk::ShaderStageFlags,
+        create_flags: vk::DescriptorSetLayoutCreateFlags,
+        name: Option<&str>,
+    ) {
+        self.validate_descriptors(descriptions, name);
+
+        // nothing to allocate, descriptors are pushed with cmd_push_descriptors every time pipe is used
+        if create_flags.contains(vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR) {
+            *descriptor_sets = Ring::new(0);
+            return;
+        }
+
         // actually setup descriptor
         unsafe {
             Self::actually_setup_descriptor_impl(
-                &self.vulkan_data.descriptor_pool,
+                &mut self.vulkan_data.descriptor_pools,
                 &self.settings,
                 &self.device,
                 &mut *dset_layout,
@@ -454,8 +593,71 @@ impl Renderer {
                 descriptions,
                 default_stages,
                 #[cfg(feature = "debug_validation_names")]
-                debug_name,
+                name,
             );
         }
+
+        // remember what was written, so update_descriptors knows what changed
+        for frame_i in 0..descriptor_sets.len() {
+            let contents = resolve_descriptor_contents(descriptions, frame_i, self.settings.fif);
+            self.descriptor_contents.insert(descriptor_sets[frame_i], contents);
+            self.descriptor_set_layouts.insert(descriptor_sets[frame_i], *dset_layout);
+        }
+    }
+
+    // pushes descriptors for current frame into cmb (resolved with same RelativeDescriptorPos rules as sets)
+    // pipe's set layout has to be created with PUSH_DESCRIPTOR_KHR. Pushed into pipe.set_index()
+    // validated like every other descriptor path (pipes carry no name, so errors are unnamed)
+    #[optimize(speed)]
+    pub fn cmd_push_descriptors<P: Pipe>(
+        &mut self,
+        cmb: &vk::CommandBuffer,
+        pipe: &P,
+        descriptions: &[DescriptorInfo],
+    ) {
+        self.validate_descriptors(descriptions, None);
+
+        let frame_i = self.vulkan_data.in_flight_fences.index;
+        let contents = resolve_descriptor_contents(descriptions, frame_i, self.settings.fif);
+
+        // fast path - cached push template
+        if let Some(data) = self
+            .descriptor_templates
+            .layout_shape(pipe.set_layout())
+            .and_then(|shape| pack_descriptor_template_data(shape, &contents))
+        {
+            if let Some(template) = self.get_descriptor_template(
+                pipe.set_layout(),
+                Some((pipe.line_layout(), pipe.bind_point(), pipe.set_index())),
+            ) {
+                unsafe {
+                    self.push_descriptors_loader.cmd_push_descriptor_set_with_template(
+                        *cmb,
+                        template,
+                        pipe.line_layout(),
+                        pipe.set_index(),
+                        data.as_ptr() as *const std::ffi::c_void,
+                    );
+                }
+                return;
+            }
+        }
+
+        let bindings: Vec<(u32, vk::DescriptorType, &DescriptorContents)> = descriptions
+            .iter()
+            .zip(&contents)
+            .enumerate()
+            .map(|(i, (desc, contents))| (desc.binding_number(i), desc.descriptor_type, contents))
+            .collect();
+
+        with_descriptor_writes(vk::DescriptorSet::null(), &bindings, |writes| unsafe {
+            self.push_descriptors_loader.cmd_push_descriptor_set(
+                *cmb,
+                pipe.bind_point(),
+                pipe.line_layout(),
+                pipe.set_index(),
+                writes,
+            );
+        });
     }
 }
@@ -0,0 +1,168 @@
+use ash::vk;
+
+// size of one texel block of a format. Uncompressed formats have 1x1 blocks
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FormatBlock {
+    pub bytes: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FormatBlock {
+    const fn texel(bytes: u32) -> Self {
+        Self {
+            bytes,
+            width: 1,
+            height: 1,
+        }
+    }
+
+    const fn block(bytes: u32, width: u32, height: u32) -> Self {
+        Self {
+            bytes,
+            width,
+            height,
+        }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.width != 1 || self.height != 1
+    }
+
+    // bytes in tightly packed row of given width (in texels)
+    pub fn row_size(&self, width: u32) -> usize {
+        width.div_ceil(self.width) as usize * self.bytes as usize
+    }
+
+    // bytes in tightly packed 2D slice of given size (in texels)
+    pub fn slice_size(&self, width: u32, height: u32) -> usize {
+        self.row_size(width) * height.div_ceil(self.height) as usize
+    }
+}
+
+// not every format, just the ones that make sense for textures and render targets
+#[rustfmt::skip]
+pub fn format_block(format: vk::Format) -> Option<FormatBlock> {
+    use vk::Format as F;
+    let block = match format {
+        F::R8_UNORM | F::R8_SNORM | F::R8_UINT | F::R8_SINT | F::R8_SRGB
+        | F::S8_UINT => FormatBlock::texel(1),
+
+        F::R8G8_UNORM | F::R8G8_SNORM | F::R8G8_UINT | F::R8G8_SINT | F::R8G8_SRGB
+        | F::R16_UNORM | F::R16_SNORM | F::R16_UINT | F::R16_SINT | F::R16_SFLOAT
+        | F::R5G6B5_UNORM_PACK16 | F::B5G6R5_UNORM_PACK16 | F::R4G4B4A4_UNORM_PACK16
+        | F::B4G4R4A4_UNORM_PACK16 | F::R5G5B5A1_UNORM_PACK16 | F::A1R5G5B5_UNORM_PACK16
+        | F::D16_UNORM => FormatBlock::texel(2),
+
+        F::R8G8B8_UNORM | F::R8G8B8_SRGB | F::B8G8R8_UNORM | F::B8G8R8_SRGB
+        | F::D16_UNORM_S8_UINT => FormatBlock::texel(3),
+
+        F::R8G8B8A8_UNORM | F::R8G8B8A8_SNORM | F::R8G8B8A8_UINT | F::R8G8B8A8_SINT
+        | F::R8G8B8A8_SRGB | F::B8G8R8A8_UNORM | F::B8G8R8A8_SNORM | F::B8G8R8A8_UINT
+        | F::B8G8R8A8_SINT | F::B8G8R8A8_SRGB | F::A8B8G8R8_UNORM_PACK32
+        | F::A8B8G8R8_SRGB_PACK32 | F::A2B10G10R10_UNORM_PACK32 | F::A2R10G10B10_UNORM_PACK32
+        | F::A2B10G10R10_UINT_PACK32 | F::B10G11R11_UFLOAT_PACK32 | F::E5B9G9R9_UFLOAT_PACK32
+        | F::R16G16_UNORM | F::R16G16_SNORM | F::R16G16_UINT | F::R16G16_SINT
+        | F::R16G16_SFLOAT | F::R32_UINT | F::R32_SINT | F::R32_SFLOAT
+        | F::D32_SFLOAT | F::D24_UNORM_S8_UINT | F::X8_D24_UNORM_PACK32 => FormatBlock::texel(4),
+
+        F::D32_SFLOAT_S8_UINT => FormatBlock::texel(5),
+
+        F::R16G16B16_UNORM | F::R16G16B16_SFLOAT => FormatBlock::texel(6),
+
+        F::R16G16B16A16_UNORM | F::R16G16B16A16_SNORM | F::R16G16B16A16_UINT
+        | F::R16G16B16A16_SINT | F::R16G16B16A16_SFLOAT | F::R32G32_UINT | F::R32G32_SINT
+        | F::R32G32_SFLOAT => FormatBlock::texel(8),
+
+        F::R32G32B32_UINT | F::R32G32B32_SINT | F::R32G32B32_SFLOAT => FormatBlock::texel(12),
+
+        F::R32G32B32A32_UINT | F::R32G32B32A32_SINT
+        | F::R32G32B32A32_SFLOAT => FormatBlock::texel(16),
+
+        F::BC1_RGB_UNORM_BLOCK | F::BC1_RGB_SRGB_BLOCK | F::BC1_RGBA_UNORM_BLOCK
+        | F::BC1_RGBA_SRGB_BLOCK | F::BC4_UNORM_BLOCK | F::BC4_SNORM_BLOCK
+        | F::ETC2_R8G8B8_UNORM_BLOCK | F::ETC2_R8G8B8_SRGB_BLOCK
+        | F::ETC2_R8G8B8A1_UNORM_BLOCK | F::ETC2_R8G8B8A1_SRGB_BLOCK
+        | F::EAC_R11_UNORM_BLOCK | F::EAC_R11_SNORM_BLOCK => FormatBlock::block(8, 4, 4),
+
+        F::BC2_UNORM_BLOCK | F::BC2_SRGB_BLOCK | F::BC3_UNORM_BLOCK | F::BC3_SRGB_BLOCK
+        | F::BC5_UNORM_BLOCK | F::BC5_SNORM_BLOCK | F::BC6H_UFLOAT_BLOCK
+        | F::BC6H_SFLOAT_BLOCK | F::BC7_UNORM_BLOCK | F::BC7_SRGB_BLOCK
+        | F::ETC2_R8G8B8A8_UNORM_BLOCK | F::ETC2_R8G8B8A8_SRGB_BLOCK
+        | F::EAC_R11G11_UNORM_BLOCK | F::EAC_R11G11_SNORM_BLOCK => FormatBlock::block(16, 4, 4),
+
+        // ASTC formats go in UNORM, SRGB pairs of growing block size, all 16 bytes
+        _ if (F::ASTC_4X4_UNORM_BLOCK.as_raw()..=F::ASTC_12X12_SRGB_BLOCK.as_raw())
+            .contains(&format.as_raw()) =>
+        {
+            const ASTC_SIZES: [(u32, u32); 14] = [
+                (4, 4), (5, 4), (5, 5), (6, 5), (6, 6), (8, 5), (8, 6),
+                (8, 8), (10, 5), (10, 6), (10, 8), (10, 10), (12, 10), (12, 12),
+            ];
+            let pair = (format.as_raw() - F::ASTC_4X4_UNORM_BLOCK.as_raw()) / 2;
+            let (width, height) = ASTC_SIZES[pair as usize];
+            FormatBlock::block(16, width, height)
+        }
+
+        _ => return None,
+    };
+    Some(block)
+}
+
+// block of single aspect, which is what buffer <-> image copies use
+// depth of depth-stencil formats is copied without stencil (D24 still takes 4 bytes), stencil is always 1 byte
+pub fn format_aspect_block(
+    format: vk::Format,
+    aspect: vk::ImageAspectFlags,
+) -> Option<FormatBlock> {
+    use vk::Format as F;
+    if aspect == vk::ImageAspectFlags::STENCIL {
+        return Some(FormatBlock::texel(1));
+    }
+    if aspect == vk::ImageAspectFlags::DEPTH {
+        match format {
+            F::D16_UNORM_S8_UINT => return Some(FormatBlock::texel(2)),
+            F::D24_UNORM_S8_UINT | F::D32_SFLOAT_S8_UINT => return Some(FormatBlock::texel(4)),
+            _ => {}
+        }
+    }
+    format_block(format)
+}
+
+// same format, other color space. None if there is no such pair
+pub fn format_srgb_pair(format: vk::Format) -> Option<vk::Format> {
+    use vk::Format as F;
+    const PAIRS: &[(vk::Format, vk::Format)] = &[
+        (F::R8_UNORM, F::R8_SRGB),
+        (F::R8G8_UNORM, F::R8G8_SRGB),
+        (F::R8G8B8_UNORM, F::R8G8B8_SRGB),
+        (F::B8G8R8_UNORM, F::B8G8R8_SRGB),
+        (F::R8G8B8A8_UNORM, F::R8G8B8A8_SRGB),
+        (F::B8G8R8A8_UNORM, F::B8G8R8A8_SRGB),
+        (F::A8B8G8R8_UNORM_PACK32, F::A8B8G8R8_SRGB_PACK32),
+        (F::BC1_RGB_UNORM_BLOCK, F::BC1_RGB_SRGB_BLOCK),
+        (F::BC1_RGBA_UNORM_BLOCK, F::BC1_RGBA_SRGB_BLOCK),
+        (F::BC2_UNORM_BLOCK, F::BC2_SRGB_BLOCK),
+        (F::BC3_UNORM_BLOCK, F::BC3_SRGB_BLOCK),
+        (F::BC7_UNORM_BLOCK, F::BC7_SRGB_BLOCK),
+        (F::ETC2_R8G8B8_UNORM_BLOCK, F::ETC2_R8G8B8_SRGB_BLOCK),
+        (F::ETC2_R8G8B8A1_UNORM_BLOCK, F::ETC2_R8G8B8A1_SRGB_BLOCK),
+        (F::ETC2_R8G8B8A8_UNORM_BLOCK, F::ETC2_R8G8B8A8_SRGB_BLOCK),
+    ];
+    for (unorm, srgb) in PAIRS {
+        if format == *unorm {
+            return Some(*srgb);
+        }
+        if format == *srgb {
+            return Some(*unorm);
+        }
+    }
+    // ASTC is interleaved UNORM, SRGB
+    let astc = format.as_raw() - F::ASTC_4X4_UNORM_BLOCK.as_raw();
+    if (0..28).contains(&astc) {
+        return Some(vk::Format::from_raw(
+            F::ASTC_4X4_UNORM_BLOCK.as_raw() + (astc ^ 1),
+        ));
+    }
+    None
+}
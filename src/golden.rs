@@ -0,0 +1,259 @@
+// golden image regression testing. Render offscreen (see Renderer::create_headless),
+// read target back and compare against reference PNG on disk
+// set LUMAL_UPDATE_GOLDEN=1 to (re)write references instead of comparing
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::capture::{convert_to_rgba8, save_rgba8};
+use crate::textures::TextureData;
+use crate::{Image, Renderer};
+use ash::vk;
+
+pub const UPDATE_GOLDEN_ENV: &str = "LUMAL_UPDATE_GOLDEN";
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GoldenReport {
+    pub max_error: u8,     // biggest per-channel difference
+    pub psnr: f64,         // in dB, infinite for identical images
+    pub mismatched: usize, // pixels with any channel off by more than tolerance
+    pub total: usize,
+    pub updated: bool, // reference was (re)written from actual because of LUMAL_UPDATE_GOLDEN, nothing compared
+}
+
+impl GoldenReport {
+    pub fn passed(&self) -> bool {
+        self.mismatched == 0
+    }
+}
+
+impl fmt::Display for GoldenReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} pixels mismatched, max error {}, PSNR {:.2} dB",
+            self.mismatched, self.total, self.max_error, self.psnr
+        )
+    }
+}
+
+// both are tightly packed RGBA8 of same size
+#[cold]
+#[optimize(size)]
+pub fn compare_rgba8(actual: &[u8], expected: &[u8], tolerance: u8) -> GoldenReport {
+    assert_eq!(actual.len(), expected.len(), "Images differ in size");
+
+    let mut report = GoldenReport {
+        total: actual.len() / 4,
+        ..Default::default()
+    };
+    let mut squared_error_sum = 0.0;
+    for (a, e) in actual.chunks_exact(4).zip(expected.chunks_exact(4)) {
+        let mut pixel_error = 0;
+        for c in 0..4 {
+            let error = a[c].abs_diff(e[c]);
+            pixel_error = pixel_error.max(error);
+            squared_error_sum += (error as f64) * (error as f64);
+        }
+        report.max_error = report.max_error.max(pixel_error);
+        if pixel_error > tolerance {
+            report.mismatched += 1;
+        }
+    }
+
+    let mse = squared_error_sum / actual.len().max(1) as f64;
+    report.psnr = if mse == 0.0 {
+        f64::INFINITY
+    } else {
+        10.0 * (255.0 * 255.0 / mse).log10()
+    };
+    report
+}
+
+// mismatched pixels are red (brighter = bigger error), matching ones are dimmed grayscale of expected
+#[cold]
+#[optimize(size)]
+pub fn diff_rgba8(actual: &[u8], expected: &[u8], tolerance: u8) -> Vec<u8> {
+    actual
+        .chunks_exact(4)
+        .zip(expected.chunks_exact(4))
+        .flat_map(|(a, e)| {
+            let error = (0..4).map(|c| a[c].abs_diff(e[c])).max().unwrap();
+            if error > tolerance {
+                [128 + error / 2, 0, 0, 255]
+            } else {
+                let gray = ((e[0] as u32 + e[1] as u32 + e[2] as u32) / 3 / 4) as u8;
+                [gray, gray, gray, 255]
+            }
+        })
+        .collect()
+}
+
+// "golden/scene.png" + "diff" -> "golden/scene.diff.png"
+fn sibling_path(reference: &Path, suffix: &str) -> PathBuf {
+    let stem = reference.file_stem().unwrap_or_default().to_string_lossy();
+    reference.with_file_name(format!("{}.{}.png", stem, suffix))
+}
+
+fn save_golden(path: &Path, width: u32, height: u32, rgba: &[u8]) {
+    save_rgba8(path, width, height, rgba)
+        .unwrap_or_else(|e| panic!("Failed to write {}: {}", path.display(), e));
+}
+
+// compares against reference PNG. With LUMAL_UPDATE_GOLDEN set overwrites it instead (report.updated)
+// On failure writes .actual.png and .diff.png next to it and panics
+#[cold]
+#[optimize(size)]
+pub fn check_golden<P: AsRef<Path>>(
+    actual: &[u8],
+    width: u32,
+    height: u32,
+    reference: P,
+    tolerance: u8,
+) -> GoldenReport {
+    let reference = reference.as_ref();
+
+    if std::env::var_os(UPDATE_GOLDEN_ENV).is_some_and(|v| v != "0") {
+        if let Some(dir) = reference.parent() {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        save_golden(reference, width, height, actual);
+        return GoldenReport {
+            updated: true,
+            ..compare_rgba8(actual, actual, tolerance)
+        };
+    }
+
+    let bytes = std::fs::read(reference).unwrap_or_else(|e| {
+        panic!(
+            "Failed to read golden image {} ({}). Run with {}=1 to create it",
+            reference.display(),
+            e,
+            UPDATE_GOLDEN_ENV
+        )
+    });
+    let expected = TextureData::from_png(&bytes, false).unwrap_or_else(|e| {
+        panic!(
+            "Failed to decode golden image {}: {}",
+            reference.display(),
+            e
+        )
+    });
+    assert!(
+        expected.format == vk::Format::R8G8B8A8_UNORM,
+        "Golden image {} is not 8 bit",
+        reference.display()
+    );
+    assert!(
+        expected.extent.width == width && expected.extent.height == height,
+        "Golden image {} is {}x{}, got {}x{}",
+        reference.display(),
+        expected.extent.width,
+        expected.extent.height,
+        width,
+        height
+    );
+
+    let expected = &expected.levels[0];
+    let report = compare_rgba8(actual, expected, tolerance);
+    if !report.passed() {
+        save_golden(&sibling_path(reference, "actual"), width, height, actual);
+        let diff = diff_rgba8(actual, expected, tolerance);
+        save_golden(&sibling_path(reference, "diff"), width, height, &diff);
+        panic!("Golden image {} mismatch: {}", reference.display(), report);
+    }
+    report
+}
+
+impl Renderer {
+    // records commands with given closure and waits for them to finish
+    // meant for headless tests, where there is no frame loop
+    #[cold]
+    #[optimize(size)]
+    pub fn render_offscreen(&mut self, record: impl FnOnce(&mut Renderer, &vk::CommandBuffer)) {
+        let cmdbuf = self.begin_single_time_command_buffer();
+        record(self, &cmdbuf);
+        self.end_single_time_command_buffer(cmdbuf);
+    }
+
+    // reads back mip 0 layer 0 of target and compares it with reference (see check_golden)
+    // target is left in TransferSrc
+    #[cold]
+    #[optimize(size)]
+    pub fn assert_golden<P: AsRef<Path>>(
+        &mut self,
+        target: &mut Image,
+        reference: P,
+        tolerance: u8,
+    ) -> GoldenReport {
+        let data = self.download_image(target, 0, 0);
+        let rgba = convert_to_rgba8(&data, target.format);
+        check_golden(
+            &rgba,
+            target.extent.width,
+            target.extent.height,
+            reference,
+            tolerance,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_images_pass() {
+        let image = [10, 20, 30, 255, 40, 50, 60, 255];
+        let report = compare_rgba8(&image, &image, 0);
+        assert!(report.passed());
+        assert_eq!(report.total, 2);
+        assert_eq!(report.max_error, 0);
+        assert_eq!(report.psnr, f64::INFINITY);
+    }
+
+    #[test]
+    fn tolerance_is_inclusive() {
+        let expected = [100, 100, 100, 255, 100, 100, 100, 255];
+        let actual = [102, 100, 100, 255, 100, 97, 100, 255];
+
+        let report = compare_rgba8(&actual, &expected, 2);
+        assert_eq!(report.mismatched, 1);
+        assert_eq!(report.max_error, 3);
+        assert!(report.psnr.is_finite());
+
+        assert!(compare_rgba8(&actual, &expected, 3).passed());
+    }
+
+    #[test]
+    fn psnr_of_known_error() {
+        // every channel off by 255 -> mse is 255^2 -> 0 dB
+        let report = compare_rgba8(&[255; 8], &[0; 8], 0);
+        assert_eq!(report.mismatched, 2);
+        assert!(report.psnr.abs() < 1e-9);
+    }
+
+    #[test]
+    #[should_panic(expected = "Images differ in size")]
+    fn size_mismatch_panics() {
+        compare_rgba8(&[0; 8], &[0; 4], 0);
+    }
+
+    #[test]
+    fn diff_marks_mismatches_red() {
+        let expected = [60, 120, 180, 255, 0, 0, 0, 255];
+        let actual = [60, 120, 181, 255, 0, 200, 0, 255];
+        let diff = diff_rgba8(&actual, &expected, 1);
+        assert_eq!(diff.len(), 8);
+        // within tolerance -> dimmed gray of expected (120 / 4)
+        assert_eq!(&diff[0..4], &[30, 30, 30, 255]);
+        // off by 200 -> 128 + 100
+        assert_eq!(&diff[4..8], &[228, 0, 0, 255]);
+    }
+
+    #[test]
+    fn sibling_paths() {
+        let path = sibling_path(Path::new("golden/scene.png"), "diff");
+        assert_eq!(path, Path::new("golden/scene.diff.png"));
+    }
+}
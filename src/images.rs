@@ -1,10 +1,186 @@
+use crate::barriers::{ImageState, ImageUsage};
+use crate::formats::format_aspect_block;
 use crate::{ring::Ring, Renderer}; // Import the LumalRenderer struct
 use crate::{set_debug_names, Image};
 use ash::vk::{self, Handle};
 use gpu_allocator::vulkan as vma;
 
 use std::ptr::{self};
+
+// everything create_layered_image needs to know. Default is single 2D layer with single mip
+#[derive(Clone, Copy, Debug)]
+pub struct ImageDesc {
+    pub image_type: vk::ImageType,
+    pub format: vk::Format,
+    pub usage: vk::ImageUsageFlags,
+    pub aspect: vk::ImageAspectFlags,
+    pub extent: vk::Extent3D,
+    pub mip_levels: u32,
+    pub layers: u32,
+    pub flags: vk::ImageCreateFlags, // CUBE_COMPATIBLE, MUTABLE_FORMAT, ...
+    pub samples: vk::SampleCountFlags,
+}
+
+impl Default for ImageDesc {
+    fn default() -> Self {
+        Self {
+            image_type: vk::ImageType::TYPE_2D,
+            format: vk::Format::UNDEFINED,
+            usage: vk::ImageUsageFlags::empty(),
+            aspect: vk::ImageAspectFlags::COLOR,
+            extent: vk::Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            },
+            mip_levels: 1,
+            layers: 1,
+            flags: vk::ImageCreateFlags::empty(),
+            samples: vk::SampleCountFlags::TYPE_1,
+        }
+    }
+}
+
+// where upload goes: box inside of one mip, in range of layers
+#[derive(Clone, Copy, Debug)]
+pub struct ImageRegion {
+    pub mip: u32,
+    pub base_layer: u32,
+    pub layer_count: u32,
+    pub offset: vk::Offset3D,
+    pub extent: vk::Extent3D,
+}
+
+impl ImageRegion {
+    // whole mip of every layer
+    pub fn whole_mip(image: &Image, mip: u32) -> Self {
+        Self {
+            mip,
+            base_layer: 0,
+            layer_count: image.layers,
+            offset: vk::Offset3D::default(),
+            extent: mip_extent(image.extent, mip),
+        }
+    }
+}
+
+// SHADER_READ_ONLY_OPTIMAL is only valid for images that can be sampled or used as input attachment
+fn uploaded_usage(image: &Image) -> ImageUsage {
+    if image
+        .usage
+        .intersects(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::INPUT_ATTACHMENT)
+    {
+        ImageUsage::ShaderRead
+    } else {
+        ImageUsage::General
+    }
+}
+
+// copies from buffer need offset that is a multiple of both texel (block) size and 4
+fn copy_offset_alignment(block_bytes: u32) -> usize {
+    let bytes = block_bytes as usize;
+    bytes * 4 / gcd(bytes, 4)
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+pub(crate) fn mip_extent(extent: vk::Extent3D, mip: u32) -> vk::Extent3D {
+    vk::Extent3D {
+        width: (extent.width >> mip).max(1),
+        height: (extent.height >> mip).max(1),
+        depth: (extent.depth >> mip).max(1),
+    }
+}
+
+// what part of image (and how) custom view looks at
+#[derive(Clone, Copy, Debug)]
+pub struct ImageViewDesc {
+    pub view_type: Option<vk::ImageViewType>, // None - figured out from layer count
+    pub format: Option<vk::Format>,           // None - image format. Other requires MUTABLE_FORMAT
+    pub aspect: Option<vk::ImageAspectFlags>, // None - image aspect
+    pub base_mip: u32,
+    pub mip_count: u32, // REMAINING_MIP_LEVELS by default
+    pub base_layer: u32,
+    pub layer_count: u32, // REMAINING_ARRAY_LAYERS by default
+    pub components: vk::ComponentMapping,
+}
+
+impl Default for ImageViewDesc {
+    fn default() -> Self {
+        Self {
+            view_type: None,
+            format: None,
+            aspect: None,
+            base_mip: 0,
+            mip_count: vk::REMAINING_MIP_LEVELS,
+            base_layer: 0,
+            layer_count: vk::REMAINING_ARRAY_LAYERS,
+            components: vk::ComponentMapping::default(),
+        }
+    }
+}
+
+impl ImageViewDesc {
+    // all mips of single layer (e.g. cube face)
+    pub fn layer(layer: u32) -> Self {
+        Self {
+            base_layer: layer,
+            layer_count: 1,
+            ..Default::default()
+        }
+    }
+
+    // single mip of all layers
+    pub fn mip(mip: u32) -> Self {
+        Self {
+            base_mip: mip,
+            mip_count: 1,
+            ..Default::default()
+        }
+    }
+}
+
+fn view_type_for(
+    image_type: vk::ImageType,
+    layers: u32,
+    flags: vk::ImageCreateFlags,
+) -> vk::ImageViewType {
+    if flags.contains(vk::ImageCreateFlags::CUBE_COMPATIBLE) {
+        assert!(
+            layers.is_multiple_of(6),
+            "Cube images need 6 layers per cube"
+        );
+        return if layers == 6 {
+            vk::ImageViewType::CUBE
+        } else {
+            vk::ImageViewType::CUBE_ARRAY
+        };
+    }
+    match (image_type, layers > 1) {
+        (vk::ImageType::TYPE_1D, false) => vk::ImageViewType::TYPE_1D,
+        (vk::ImageType::TYPE_1D, true) => vk::ImageViewType::TYPE_1D_ARRAY,
+        (vk::ImageType::TYPE_2D, false) => vk::ImageViewType::TYPE_2D,
+        (vk::ImageType::TYPE_2D, true) => vk::ImageViewType::TYPE_2D_ARRAY,
+        (vk::ImageType::TYPE_3D, false) => vk::ImageViewType::TYPE_3D,
+        _ => panic!("Unsupported image type"),
+    }
+}
+
 impl Renderer {
+    // CUBE_ARRAY views need imageCubeArray feature
+    fn assert_view_type_supported(&self, view_type: vk::ImageViewType) {
+        assert!(
+            view_type != vk::ImageViewType::CUBE_ARRAY || self.vulkan_data.image_cube_array,
+            "CUBE_ARRAY views (more than one cube) need imageCubeArray, which device does not support"
+        );
+    }
+
     #[cold]
     #[optimize(speed)]
     pub fn create_image(
@@ -20,17 +196,90 @@ impl Renderer {
         sample_count: vk::SampleCountFlags,
         #[cfg(feature = "debug_validation_names")] debug_name: Option<&str>,
     ) -> Image {
+        self.create_layered_image(
+            &ImageDesc {
+                image_type,
+                format,
+                usage,
+                aspect,
+                extent,
+                mip_levels: mipmaps,
+                samples: sample_count,
+                ..Default::default()
+            },
+            #[cfg(feature = "debug_validation_names")]
+            debug_name,
+        )
+    }
+
+    // cube map (cubes == 1) or cube map array (cubes > 1), each cube is 6 layers
+    #[cold]
+    #[optimize(speed)]
+    pub fn create_cube_image(
+        &mut self,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        aspect: vk::ImageAspectFlags,
+        size: u32,
+        mipmaps: u32,
+        cubes: u32,
+        #[cfg(feature = "debug_validation_names")] debug_name: Option<&str>,
+    ) -> Image {
+        self.create_layered_image(
+            &ImageDesc {
+                format,
+                usage,
+                aspect,
+                extent: vk::Extent3D {
+                    width: size,
+                    height: size,
+                    depth: 1,
+                },
+                mip_levels: mipmaps,
+                layers: cubes * 6,
+                flags: vk::ImageCreateFlags::CUBE_COMPATIBLE,
+                ..Default::default()
+            },
+            #[cfg(feature = "debug_validation_names")]
+            debug_name,
+        )
+    }
+
+    // main view covers all layers: *_ARRAY for layers > 1, CUBE / CUBE_ARRAY for CUBE_COMPATIBLE
+    // pass MUTABLE_FORMAT in flags to later create views with different format
+    #[cold]
+    #[optimize(speed)]
+    pub fn create_layered_image(
+        &mut self,
+        desc: &ImageDesc,
+        #[cfg(feature = "debug_validation_names")] debug_name: Option<&str>,
+    ) -> Image {
+        let ImageDesc {
+            image_type,
+            format,
+            usage,
+            aspect,
+            extent,
+            mip_levels: mipmaps,
+            layers,
+            flags,
+            samples: sample_count,
+        } = *desc;
+        assert!(layers >= 1);
+        let view_type = view_type_for(image_type, layers, flags);
+        self.assert_view_type_supported(view_type);
         let image_aspect = aspect;
         let image_format = format;
         let image_extent = extent;
         let image_mip_levels = mipmaps;
 
         let image_info = vk::ImageCreateInfo {
+            flags,
             image_type,
             format,
             extent,
             mip_levels: mipmaps,
-            array_layers: 1,
+            array_layers: layers,
             samples: sample_count,
             tiling: vk::ImageTiling::OPTIMAL,
             usage,
@@ -59,13 +308,6 @@ impl Renderer {
                 .unwrap()
         };
 
-        let view_type = match image_type {
-            vk::ImageType::TYPE_1D => vk::ImageViewType::TYPE_1D,
-            vk::ImageType::TYPE_2D => vk::ImageViewType::TYPE_2D,
-            vk::ImageType::TYPE_3D => vk::ImageViewType::TYPE_3D,
-            _ => return panic!("Unsupported image type"),
-        };
-
         let mut view_info = vk::ImageViewCreateInfo {
             flags: vk::ImageViewCreateFlags::empty(),
             image: vk_image,
@@ -83,7 +325,7 @@ impl Renderer {
                 base_mip_level: 0,
                 level_count: mipmaps,
                 base_array_layer: 0,
-                layer_count: 1,
+                layer_count: layers,
             },
             ..Default::default()
         };
@@ -103,15 +345,21 @@ impl Renderer {
                 .collect::<Vec<_>>();
         }
 
-        let image = Image {
+        let mut image = Image {
             image: vk_image,
             allocation: allocation,
             view: image_view,
             mip_views: image_mip_views,
             format: image_format,
             aspect: image_aspect,
+            image_type,
             extent: image_extent,
             mip_levels: image_mip_levels,
+            layers,
+            flags,
+            usage,
+            views: vec![],
+            states: vec![ImageState::default(); (image_mip_levels * layers) as usize],
         };
 
         self.transition_image_layout_single_time(
@@ -119,6 +367,7 @@ impl Renderer {
             vk::ImageLayout::UNDEFINED,
             vk::ImageLayout::GENERAL,
         );
+        image.assume_usage(ImageUsage::General);
 
         set_debug_names!(
             self,
@@ -174,10 +423,239 @@ impl Renderer {
         }
     }
 
+    // creates additional view of image. It is owned by image and destroyed with it
+    #[cold]
+    #[optimize(size)]
+    pub fn create_image_view(&self, image: &mut Image, desc: &ImageViewDesc) -> vk::ImageView {
+        let format = desc.format.unwrap_or(image.format);
+        assert!(
+            format == image.format || image.flags.contains(vk::ImageCreateFlags::MUTABLE_FORMAT),
+            "View format differs from image format, but image was not created with MUTABLE_FORMAT"
+        );
+
+        let range = image.resolve_range(vk::ImageSubresourceRange {
+            aspect_mask: desc.aspect.unwrap_or(image.aspect),
+            base_mip_level: desc.base_mip,
+            level_count: desc.mip_count,
+            base_array_layer: desc.base_layer,
+            layer_count: desc.layer_count,
+        });
+
+        // cube views need whole cubes, everything else just gets array / non-array type
+        let flags = if range.layer_count.is_multiple_of(6) {
+            image.flags
+        } else {
+            image.flags & !vk::ImageCreateFlags::CUBE_COMPATIBLE
+        };
+        let view_type = desc
+            .view_type
+            .unwrap_or_else(|| view_type_for(image.image_type, range.layer_count, flags));
+        self.assert_view_type_supported(view_type);
+
+        let view_info = vk::ImageViewCreateInfo {
+            image: image.image,
+            view_type,
+            format,
+            components: desc.components,
+            subresource_range: range,
+            ..Default::default()
+        };
+
+        let view = unsafe { self.device.create_image_view(&view_info, None).unwrap() };
+        image.views.push(view);
+        view
+    }
+
+    // creates image and uploads CPU pixels into it. levels[mip] is (data, row_pitch) of that mip,
+    // with all layers following each other (see upload_image_region). Mips past levels are left empty
+    // (e.g. for cmd_generate_mipmaps). Does usage |= TRANSFER_DST automatically
+    // image is left in ShaderRead (General if it has neither SAMPLED nor INPUT_ATTACHMENT usage)
+    #[cold]
+    #[optimize(size)]
+    pub fn create_and_upload_image(
+        &mut self,
+        desc: &ImageDesc,
+        levels: &[(&[u8], usize)],
+        #[cfg(feature = "debug_validation_names")] debug_name: Option<&str>,
+    ) -> Image {
+        assert!(
+            levels.len() as u32 <= desc.mip_levels,
+            "More mip levels of data than image has"
+        );
+        let mut image = self.create_layered_image(
+            &ImageDesc {
+                usage: desc.usage | vk::ImageUsageFlags::TRANSFER_DST,
+                ..*desc
+            },
+            #[cfg(feature = "debug_validation_names")]
+            debug_name,
+        );
+
+        let cmdbuf = self.begin_single_time_command_buffer();
+        self.begin_single_time_staging();
+        for (mip, (data, row_pitch)) in levels.iter().enumerate() {
+            let region = ImageRegion::whole_mip(&image, mip as u32);
+            self.cmd_upload_image(&cmdbuf, &mut image, data, *row_pitch, &region);
+        }
+        // mips without data too, so whole image ends up in same state
+        let usage = uploaded_usage(&image);
+        self.transition(&cmdbuf, &mut image, usage);
+        self.end_single_time_command_buffer(cmdbuf);
+        self.end_single_time_staging();
+
+        image
+    }
+
+    // copies CPU pixels into (part of) one mip of given layers through staging buffer
+    // row_pitch is in bytes (0 = tightly packed). For compressed formats a row is a row of blocks
+    // each layer starts right after previous one ends (row_pitch * rows * depth bytes)
+    // depth-stencil images only get depth uploaded (4 bytes per texel for D24 / D32 with stencil)
+    // uploaded subresources are left in ShaderRead (General if image can not be read as SAMPLED
+    // or INPUT_ATTACHMENT, e.g. storage-only), everything else keeps its state
+    // waits for GPU, use cmd_upload_image inside of frame
+    #[cold]
+    #[optimize(size)]
+    pub fn upload_image_region(
+        &mut self,
+        image: &mut Image,
+        data: &[u8],
+        row_pitch: usize,
+        region: &ImageRegion,
+    ) {
+        let cmdbuf = self.begin_single_time_command_buffer();
+        self.begin_single_time_staging();
+        self.cmd_upload_image(&cmdbuf, image, data, row_pitch, region);
+        self.end_single_time_command_buffer(cmdbuf);
+        self.end_single_time_staging();
+    }
+
+    // same as upload_image_region, but recorded into cmdbuf. Staging space comes from per-frame arena
+    #[cold]
+    #[optimize(size)]
+    pub fn cmd_upload_image(
+        &mut self,
+        cmdbuf: &vk::CommandBuffer,
+        image: &mut Image,
+        data: &[u8],
+        row_pitch: usize,
+        region: &ImageRegion,
+    ) {
+        let ImageRegion {
+            mip,
+            base_layer,
+            layer_count,
+            offset,
+            extent,
+        } = *region;
+
+        // depth and stencil have to be copied separately, we only upload depth
+        let aspect_mask = if image.aspect.contains(vk::ImageAspectFlags::DEPTH) {
+            vk::ImageAspectFlags::DEPTH
+        } else {
+            image.aspect
+        };
+        let block = format_aspect_block(image.format, aspect_mask)
+            .unwrap_or_else(|| panic!("Unknown texel size of format {:?}", image.format));
+
+        assert!(mip < image.mip_levels, "Mip {} out of range", mip);
+        assert!(
+            base_layer + layer_count <= image.layers,
+            "Layers out of range"
+        );
+
+        let mip_size = mip_extent(image.extent, mip);
+        assert!(
+            offset.x as u32 + extent.width <= mip_size.width
+                && offset.y as u32 + extent.height <= mip_size.height
+                && offset.z as u32 + extent.depth <= mip_size.depth,
+            "Region does not fit into mip {}",
+            mip
+        );
+        // compressed formats can only be copied by whole blocks (partial ones only at mip edge)
+        assert!(
+            (offset.x as u32).is_multiple_of(block.width)
+                && (offset.y as u32).is_multiple_of(block.height),
+            "Region offset is not a multiple of {}x{} block",
+            block.width,
+            block.height
+        );
+        assert!(
+            (extent.width.is_multiple_of(block.width)
+                || offset.x as u32 + extent.width == mip_size.width)
+                && (extent.height.is_multiple_of(block.height)
+                    || offset.y as u32 + extent.height == mip_size.height),
+            "Region extent is not a multiple of {}x{} block and does not reach mip edge",
+            block.width,
+            block.height
+        );
+
+        let tight_pitch = block.row_size(extent.width);
+        let row_pitch = if row_pitch == 0 {
+            tight_pitch
+        } else {
+            row_pitch
+        };
+        assert!(row_pitch >= tight_pitch, "Row pitch is smaller than row");
+        assert!(
+            row_pitch.is_multiple_of(block.bytes as usize),
+            "Row pitch must be a multiple of texel (block) size"
+        );
+
+        let rows = extent.height.div_ceil(block.height) as usize * extent.depth as usize;
+        let layer_size = row_pitch * rows;
+        // last row does not have to be padded
+        let needed = layer_size * layer_count as usize - (row_pitch - tight_pitch);
+        assert!(
+            data.len() >= needed,
+            "Not enough pixel data: {} < {}",
+            data.len(),
+            needed
+        );
+
+        // buffer offset has to be a multiple of both texel size and 4, layer size might not be
+        // (3x3 of RGB8 is 27 bytes), so every layer is staged and copied on its own
+        let alignment = copy_offset_alignment(block.bytes);
+        let range = image.subresource_range(mip, 1, base_layer, layer_count);
+
+        self.transition_range(cmdbuf, image, range, ImageUsage::TransferDst);
+        for i in 0..layer_count {
+            let start = i as usize * layer_size;
+            let end = (start + layer_size).min(needed);
+            let (staging, staging_offset) = self.stage(&data[start..end], alignment);
+            let region = vk::BufferImageCopy {
+                buffer_offset: staging_offset,
+                buffer_row_length: (row_pitch / block.bytes as usize) as u32 * block.width,
+                buffer_image_height: 0, // = extent.height
+                image_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask,
+                    mip_level: mip,
+                    base_array_layer: base_layer + i,
+                    layer_count: 1,
+                },
+                image_offset: offset,
+                image_extent: extent,
+            };
+            unsafe {
+                self.device.cmd_copy_buffer_to_image(
+                    *cmdbuf,
+                    staging,
+                    image.image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[region],
+                );
+            }
+        }
+        let usage = uploaded_usage(image);
+        self.transition_range(cmdbuf, image, range, usage);
+    }
+
     #[cold]
     #[optimize(speed)]
     pub fn destroy_image(&mut self, img: Image) {
         unsafe {
+            for view in img.mip_views.iter().chain(img.views.iter()) {
+                self.device.destroy_image_view(*view, None);
+            }
             self.device.destroy_image_view(img.view, None);
             self.allocator.free(img.allocation).unwrap();
             self.device.destroy_image(img.image, None);
@@ -0,0 +1,172 @@
+// hashed, reference counted caches of descriptor set layouts and pipeline layouts
+// identical binding shapes get the same vk::DescriptorSetLayout, identical (set layouts, push ranges) the same
+// vk::PipelineLayout. So pipes with same layout are compatible - sets bound for one stay valid for the other
+// everything still alive is destroyed with renderer
+// lives in RefCell, because pipes (and mipmap pipe) are created through &self
+
+use std::collections::HashMap;
+
+use crate::descriptor_templates::DescriptorShape;
+use crate::descriptors::ShortDescriptorInfo;
+use crate::Renderer;
+use ash::vk;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineLayoutKey {
+    pub set_layouts: Vec<vk::DescriptorSetLayout>,
+    // (stages, offset, size) of every push constant range
+    pub push_ranges: Vec<(vk::ShaderStageFlags, u32, u32)>,
+}
+
+#[derive(Debug, Default)]
+pub struct LayoutCache {
+    set_layouts: HashMap<DescriptorShape, (vk::DescriptorSetLayout, u32)>, // (layout, refcount)
+    set_layout_shapes: HashMap<vk::DescriptorSetLayout, DescriptorShape>,
+    pipeline_layouts: HashMap<PipelineLayoutKey, (vk::PipelineLayout, u32)>, // (layout, refcount)
+    pipeline_layout_keys: HashMap<vk::PipelineLayout, PipelineLayoutKey>,
+}
+
+impl LayoutCache {
+    pub fn set_layout_count(&self) -> usize {
+        self.set_layouts.len()
+    }
+
+    pub fn pipeline_layout_count(&self) -> usize {
+        self.pipeline_layouts.len()
+    }
+
+    // layout came from this cache (and was not destroyed yet)
+    pub fn owns_set_layout(&self, layout: vk::DescriptorSetLayout) -> bool {
+        self.set_layout_shapes.contains_key(&layout)
+    }
+
+    pub fn owns_pipeline_layout(&self, layout: vk::PipelineLayout) -> bool {
+        self.pipeline_layout_keys.contains_key(&layout)
+    }
+
+    // what pipeline layout was created from
+    pub fn pipeline_layout_key(&self, layout: vk::PipelineLayout) -> Option<&PipelineLayoutKey> {
+        self.pipeline_layout_keys.get(&layout)
+    }
+}
+
+impl Renderer {
+    // existing layout with same shape (refcount += 1) or new one
+    // descriptors are counted for pool anyway, since every user allocates its own sets
+    #[cold]
+    #[optimize(size)]
+    pub fn acquire_descriptor_set_layout(
+        &mut self,
+        descriptor_infos: &[ShortDescriptorInfo],
+        flags: vk::DescriptorSetLayoutCreateFlags,
+        #[cfg(feature = "debug_validation_names")] debug_name: Option<&str>,
+    ) -> vk::DescriptorSetLayout {
+        let shape = DescriptorShape::new(descriptor_infos, flags);
+        if let Some((layout, refcount)) = self.layout_cache.get_mut().set_layouts.get_mut(&shape) {
+            *refcount += 1;
+            let layout = *layout;
+            self.count_descriptors(descriptor_infos, flags);
+            return layout;
+        }
+
+        let mut layout = vk::DescriptorSetLayout::null();
+        self.create_descriptor_set_layout(
+            descriptor_infos,
+            &mut layout,
+            flags,
+            #[cfg(feature = "debug_validation_names")]
+            debug_name,
+        );
+        let cache = self.layout_cache.get_mut();
+        cache.set_layout_shapes.insert(layout, shape.clone());
+        cache.set_layouts.insert(shape, (layout, 1));
+        layout
+    }
+
+    // refcount -= 1, destroyed when nobody uses it. Layouts not from cache are destroyed right away
+    #[cold]
+    #[optimize(size)]
+    pub fn release_descriptor_set_layout(&mut self, layout: vk::DescriptorSetLayout) {
+        let cache = self.layout_cache.get_mut();
+        if let Some(shape) = cache.set_layout_shapes.get(&layout) {
+            let (_, refcount) = cache.set_layouts.get_mut(shape).unwrap();
+            *refcount -= 1;
+            if *refcount > 0 {
+                return;
+            }
+            let shape = cache.set_layout_shapes.remove(&layout).unwrap();
+            cache.set_layouts.remove(&shape);
+        }
+        self.forget_set_layout_templates(layout);
+        unsafe { self.device.destroy_descriptor_set_layout(layout, None) };
+    }
+
+    #[cold]
+    #[optimize(size)]
+    pub fn acquire_pipeline_layout(
+        &self,
+        set_layouts: &[vk::DescriptorSetLayout],
+        push_ranges: &[vk::PushConstantRange],
+    ) -> vk::PipelineLayout {
+        let key = PipelineLayoutKey {
+            set_layouts: set_layouts.to_vec(),
+            push_ranges: push_ranges
+                .iter()
+                .map(|range| (range.stage_flags, range.offset, range.size))
+                .collect(),
+        };
+        let mut cache = self.layout_cache.borrow_mut();
+        if let Some((layout, refcount)) = cache.pipeline_layouts.get_mut(&key) {
+            *refcount += 1;
+            return *layout;
+        }
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo {
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            push_constant_range_count: push_ranges.len() as u32,
+            p_push_constant_ranges: push_ranges.as_ptr(),
+            ..Default::default()
+        };
+        let layout = unsafe {
+            self.device
+                .create_pipeline_layout(&pipeline_layout_info, None)
+                .expect("Failed to create pipeline layout")
+        };
+        cache.pipeline_layout_keys.insert(layout, key.clone());
+        cache.pipeline_layouts.insert(key, (layout, 1));
+        layout
+    }
+
+    #[cold]
+    #[optimize(size)]
+    pub fn release_pipeline_layout(&mut self, layout: vk::PipelineLayout) {
+        let cache = self.layout_cache.get_mut();
+        if let Some(key) = cache.pipeline_layout_keys.get(&layout) {
+            let (_, refcount) = cache.pipeline_layouts.get_mut(key).unwrap();
+            *refcount -= 1;
+            if *refcount > 0 {
+                return;
+            }
+            let key = cache.pipeline_layout_keys.remove(&layout).unwrap();
+            cache.pipeline_layouts.remove(&key);
+        }
+        self.forget_pipeline_layout_templates(layout);
+        unsafe { self.device.destroy_pipeline_layout(layout, None) };
+    }
+
+    // whatever is still referenced at exit
+    #[cold]
+    #[optimize(size)]
+    pub fn destroy_layout_cache(&mut self) {
+        let cache = self.layout_cache.get_mut();
+        for (_, (layout, _)) in cache.pipeline_layouts.drain() {
+            unsafe { self.device.destroy_pipeline_layout(layout, None) };
+        }
+        for (_, (layout, _)) in cache.set_layouts.drain() {
+            unsafe { self.device.destroy_descriptor_set_layout(layout, None) };
+        }
+        cache.pipeline_layout_keys.clear();
+        cache.set_layout_shapes.clear();
+    }
+}
@@ -5,24 +5,44 @@
 // lumal is divided into files (aka modules)
 // this in needed for whole thing to compile
 // Rust is so good that figuring it out only took 1 hour
+pub mod arena;
 pub mod barriers;
+pub mod bindless;
 pub mod blit_copy;
 pub mod buffers;
+pub mod capture;
+pub mod descriptor_pool;
+pub mod descriptor_templates;
+pub mod descriptor_updates;
+pub mod descriptor_validation;
 pub mod descriptors;
+pub mod formats;
+pub mod golden;
 pub mod images;
+pub mod layout_cache;
 pub mod macros;
+pub mod mipmaps;
 pub mod pipes;
+pub mod queues;
+pub mod readback;
 pub mod renderer;
 pub mod ring; // circular Vec
 pub mod rpass;
 pub mod samplers;
+pub mod staging;
+pub mod textures;
 
+use barriers::ImageState;
+use capture::{CaptureRequest, PendingCapture};
+use descriptor_templates::DescriptorTemplateCache;
+use descriptor_updates::{DescriptorContents, PendingDescriptorWrite};
 use ring::*;
+use staging::StagingArena;
 
 pub use ash::vk;
 use ash::{
     ext::debug_utils,
-    khr::{push_descriptor, surface, swapchain},
+    khr::{push_descriptor, surface, swapchain, synchronization2},
     prelude::VkResult,
     vk::{
         ConformanceVersion, DebugUtilsObjectNameInfoEXT, ImageAspectFlags, EXT_DEBUG_UTILS_NAME,
@@ -38,7 +58,10 @@ use std::{
     mem::{size_of, size_of_val},
 };
 use std::{any::TypeId, ffi::CStr};
-use std::{collections::HashSet, default};
+use std::{
+    collections::{HashMap, HashSet},
+    default,
+};
 use std::{ffi::c_char, process::exit};
 use winit::{
     application::ApplicationHandler,
@@ -87,10 +110,36 @@ const MAX_FRAMES_IN_FLIGHT: usize = 2;
 //     cloned_value
 // }
 
+// where buffer memory lives. Maps to gpu_allocator locations, except DeviceHostVisible
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MemoryLocation {
+    #[default]
+    GpuOnly,
+    CpuToGpu, // mapped, for uploads and small per-frame data
+    GpuToCpu, // mapped and cached, for readback
+    // mapped VRAM (ReBAR / UMA). Falls back to CpuToGpu if device has no such memory
+    DeviceHostVisible,
+}
+
+impl MemoryLocation {
+    pub fn is_mapped(self) -> bool {
+        self != MemoryLocation::GpuOnly
+    }
+}
+
 #[derive(Debug)]
 pub struct Buffer {
     pub buffer: vk::Buffer,
     pub allocation: vma::Allocation,
+    // requested size, allocation might be bigger
+    pub size: vk::DeviceSize,
+    pub location: MemoryLocation,
+    // what buffer was created for, checked when it is bound to descriptors
+    pub usage: vk::BufferUsageFlags,
+    // texel buffer views, see create_buffer_view. Owned by buffer and destroyed with it
+    pub views: Vec<vk::BufferView>,
+    // 0 unless buffer device address is enabled, see device_address()
+    pub address: vk::DeviceAddress,
     // pub mapped: Option<*mut c_void>, // If allocation is mapped
 }
 // impl Clone for Buffer {
@@ -103,11 +152,27 @@ pub struct Buffer {
 //         }
 //     }
 // }
+impl Buffer {
+    // GPU pointer to start of buffer, for passing through push constants
+    pub fn device_address(&self) -> vk::DeviceAddress {
+        assert!(
+            self.address != 0,
+            "Buffer has no device address, enable LumalSettings::buffer_device_address"
+        );
+        self.address
+    }
+}
+
 impl Default for Buffer {
     fn default() -> Self {
         Self {
             buffer: Default::default(),
             allocation: unsafe { std::mem::zeroed() },
+            size: 0,
+            location: Default::default(),
+            usage: vk::BufferUsageFlags::empty(),
+            views: vec![],
+            address: 0,
             // mapped: Default::default(),
         }
     }
@@ -121,8 +186,15 @@ pub struct Image {
     pub mip_views: Vec<vk::ImageView>, // Vec for mip views
     pub format: vk::Format,
     pub aspect: vk::ImageAspectFlags,
+    pub image_type: vk::ImageType,
     pub extent: vk::Extent3D,
     pub mip_levels: u32,
+    pub layers: u32,
+    pub flags: vk::ImageCreateFlags,
+    pub usage: vk::ImageUsageFlags, // checked when image is bound to descriptors
+    pub views: Vec<vk::ImageView>,  // custom views, see create_image_view
+    // current layout / last access of every subresource, indexed by Image::state_index
+    pub states: Vec<ImageState>,
 }
 
 impl Default for Image {
@@ -134,8 +206,14 @@ impl Default for Image {
             mip_views: Default::default(),
             format: Default::default(),
             aspect: Default::default(),
+            image_type: vk::ImageType::TYPE_2D,
             extent: Default::default(),
             mip_levels: Default::default(),
+            layers: Default::default(),
+            flags: Default::default(),
+            usage: Default::default(),
+            views: Default::default(),
+            states: Default::default(),
         }
     }
 }
@@ -148,6 +226,7 @@ pub struct RasterPipe {
     // WHERE IS MY FUCKING DEFAULT VALUE WHY NO ONE WRITES BINDINGS THAT JUST WORK
     pub sets: Ring<vk::DescriptorSet>,
     pub set_layout: vk::DescriptorSetLayout,
+    pub set_index: u32, // where sets (and pushed descriptors) go in pipeline layout
     pub render_pass: vk::RenderPass, // We don't need to store it in here but why not
     pub subpass_id: i32,
 }
@@ -167,6 +246,7 @@ impl Default for RasterPipe {
             line: Default::default(),
             line_layout: Default::default(),
             set_layout: Default::default(),
+            set_index: 0,
             render_pass: Default::default(),
             subpass_id: Default::default(),
         }
@@ -181,6 +261,7 @@ pub struct ComputePipe {
     pub line_layout: vk::PipelineLayout,
     pub sets: Ring<vk::DescriptorSet>,
     pub set_layout: vk::DescriptorSetLayout,
+    pub set_index: u32, // where sets (and pushed descriptors) go in pipeline layout
 }
 impl Default for ComputePipe {
     fn default() -> Self {
@@ -189,10 +270,54 @@ impl Default for ComputePipe {
             line_layout: Default::default(),
             sets: Default::default(),
             set_layout: Default::default(),
+            set_index: 0,
         }
     }
 }
 
+// what binding / pushing descriptors needs to know about pipe
+pub trait Pipe {
+    fn bind_point(&self) -> vk::PipelineBindPoint;
+    fn line_layout(&self) -> vk::PipelineLayout;
+    fn set_layout(&self) -> vk::DescriptorSetLayout;
+    fn set_index(&self) -> u32;
+}
+
+// one set of pipeline layout, in set index order (e.g. global, per-pass, pipe's own, per-draw)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PipeSetLayout {
+    Own,                               // pipe.set_layout, pipe.sets are bound here
+    External(vk::DescriptorSetLayout), // sets bound by user with bind_*_pipe_with_sets / cmd_bind_sets
+}
+impl Pipe for RasterPipe {
+    fn bind_point(&self) -> vk::PipelineBindPoint {
+        vk::PipelineBindPoint::GRAPHICS
+    }
+    fn line_layout(&self) -> vk::PipelineLayout {
+        self.line_layout
+    }
+    fn set_layout(&self) -> vk::DescriptorSetLayout {
+        self.set_layout
+    }
+    fn set_index(&self) -> u32 {
+        self.set_index
+    }
+}
+impl Pipe for ComputePipe {
+    fn bind_point(&self) -> vk::PipelineBindPoint {
+        vk::PipelineBindPoint::COMPUTE
+    }
+    fn line_layout(&self) -> vk::PipelineLayout {
+        self.line_layout
+    }
+    fn set_layout(&self) -> vk::DescriptorSetLayout {
+        self.set_layout
+    }
+    fn set_index(&self) -> u32 {
+        self.set_index
+    }
+}
+
 // Structure for RenderPass
 pub struct RenderPass {
     pub clear_colors: Vec<vk::ClearValue>,   // Colors to clear
@@ -255,6 +380,10 @@ pub struct LumalSettings {
     pub fullscreen: bool,
     pub debug: bool,
     pub profile: bool,
+    // opt-in, every buffer gets SHADER_DEVICE_ADDRESS and Buffer::device_address() works
+    pub buffer_device_address: bool,
+    // opt-in, descriptor indexing features for BindlessTable and partially bound / variable arrays
+    pub bindless: bool,
     // pub device_features: vk::PhysicalDeviceFeatures,
     // pub device_features11: vk::PhysicalDeviceVulkan11Features,
     // pub device_features12: vk::PhysicalDeviceVulkan12Features,
@@ -272,6 +401,8 @@ impl LumalSettings {
             fullscreen: false,
             debug: false,
             profile: false,
+            buffer_device_address: false,
+            bindless: false,
             // device_features: vk::PhysicalDeviceFeatures::default(),
             // device_features11: vk::PhysicalDeviceVulkan11Features::default(),
             // device_features12: vk::PhysicalDeviceVulkan12Features::default(),
@@ -303,6 +434,7 @@ pub struct ImageDeletion {
     pub allocation: vma::Allocation,
     pub view: vk::ImageView,           // Main view
     pub mip_views: Vec<vk::ImageView>, // Vec for mip views
+    pub views: Vec<vk::ImageView>,     // custom views
     pub lifetime: i32,
 }
 
@@ -312,6 +444,7 @@ impl Clone for ImageDeletion {
             image: self.image.clone(),
             view: self.view.clone(),
             mip_views: self.mip_views.clone(),
+            views: self.views.clone(),
             lifetime: self.lifetime,
             allocation: vma::Allocation::default(),
         }
@@ -337,11 +470,35 @@ pub struct Renderer {
     pub debug_utils_loader: debug_utils::Instance,
     pub debug_utils_device_loader: debug_utils::Device,
     pub push_descriptors_loader: push_descriptor::Device,
+    // only loaded when synchronization2 comes from extension. With Vulkan 1.3 core device is used
+    pub synchronization2_loader: Option<synchronization2::Device>,
+    // internal downsampling pipe for formats that can not be blitted. Created on first use
+    pub mipmap_pipe: std::cell::OnceCell<ComputePipe>,
+    // frame capture requested with capture_frame / capture_frames, recorded in end_frame
+    pub capture_request: Option<CaptureRequest>,
+    // recorded captures, waiting for their frame to finish before being written to disk
+    pub pending_captures: Vec<PendingCapture>,
+    pub capture_writer: capture::CaptureWriter,
+    // per-frame staging memory for uploads, see staging.rs
+    pub staging: StagingArena,
+    // extra semaphores next end_frame submission waits on (e.g. async compute), see wait_in_frame
+    pub frame_wait_semaphores: Vec<(vk::Semaphore, vk::PipelineStageFlags)>,
     pub frame: i32, // global counter of rendered frame, mostly for internal use
+    // last frame whose fence was waited in start_frame, everything it did on GPU is done
+    pub completed_frame: i32,
     pub image_index: u32,
     pub should_recreate: bool,
     pub descriptor_counter: DescriptorCounter,
     pub descriptor_sets_count: u32,
+    // last written contents of every set, see update_descriptors
+    pub descriptor_contents: HashMap<vk::DescriptorSet, Vec<DescriptorContents>>,
+    // deferred descriptor writes, applied in start_frame once their frame is not in flight
+    pub pending_descriptor_writes: Vec<PendingDescriptorWrite>,
+    // layout every set was allocated with, to find its update template
+    pub descriptor_set_layouts: HashMap<vk::DescriptorSet, vk::DescriptorSetLayout>,
+    pub descriptor_templates: DescriptorTemplateCache,
+    // shared set / pipeline layouts, see layout_cache.rs
+    pub layout_cache: std::cell::RefCell<layout_cache::LayoutCache>,
 
     pub main_command_buffers: Ring<vk::CommandBuffer>, // yep, copied
     pub extra_command_buffers: Ring<vk::CommandBuffer>, // yep, copied
@@ -357,10 +514,27 @@ impl Renderer {
     #[cold]
     #[optimize(size)]
     pub fn create(settings: &LumalSettings, window: &Window) -> Renderer {
+        Self::create_impl(settings, Some(window))
+    }
+
+    // renderer without window, surface and swapchain. For offscreen rendering and tests (lavapipe works)
+    // frames are ended with end_headless_frame instead of end_frame
+    #[cold]
+    #[optimize(size)]
+    pub fn create_headless(settings: &LumalSettings) -> Renderer {
+        Self::create_impl(settings, None)
+    }
+
+    #[cold]
+    #[optimize(size)]
+    fn create_impl(settings: &LumalSettings, window: Option<&Window>) -> Renderer {
         println!("Starting app.");
 
         let mut vulkan_data = VulkanData {
             validation: settings.debug,
+            headless: window.is_none(),
+            buffer_device_address: settings.buffer_device_address,
+            bindless: settings.bindless,
             ..Default::default()
         };
 
@@ -370,14 +544,16 @@ impl Renderer {
         unsafe {
             let entry = Entry::load().expect("Failed to load Vulkan entry point");
             let instance = Renderer::create_instance(window, &entry, &mut vulkan_data);
-            vulkan_data.surface = ash_window::create_surface(
-                &entry,
-                &instance,
-                window.display_handle().unwrap().as_raw(),
-                window.window_handle().unwrap().as_raw(),
-                None,
-            )
-            .unwrap();
+            if let Some(window) = window {
+                vulkan_data.surface = ash_window::create_surface(
+                    &entry,
+                    &instance,
+                    window.display_handle().unwrap().as_raw(),
+                    window.window_handle().unwrap().as_raw(),
+                    None,
+                )
+                .unwrap();
+            }
             pick_physical_device(&instance, &entry, &mut vulkan_data);
             let device = create_logical_device(&entry, &instance, &mut vulkan_data);
 
@@ -386,12 +562,14 @@ impl Renderer {
                 device: device.clone(),
                 physical_device: vulkan_data.physical_device,
                 debug_settings: Default::default(),
-                buffer_device_address: false,
+                buffer_device_address: vulkan_data.buffer_device_address,
                 allocation_sizes: Default::default(),
             })
             .unwrap();
 
-            create_swapchain(window, &instance, &entry, &device, &mut vulkan_data);
+            if let Some(window) = window {
+                create_swapchain(window, &instance, &entry, &device, &mut vulkan_data);
+            }
             // create_swapchain_image_views(&device, &mut vulkan_data);
             // these are handled by downstream user. Makes no sense to hardcode pipes in renderer
             // example.create_render_pass(&device, &mut data);
@@ -406,6 +584,11 @@ impl Renderer {
             let debug_utils_loader = debug_utils::Instance::new(&entry, &instance);
             let debug_utils_device_loader = debug_utils::Device::new(&instance, &device);
             let push_descriptors_loader = push_descriptor::Device::new(&instance, &device);
+            let synchronization2_loader = if vulkan_data.synchronization2_khr {
+                Some(synchronization2::Device::new(&instance, &device))
+            } else {
+                None
+            };
 
             Renderer {
                 allocator,
@@ -414,10 +597,16 @@ impl Renderer {
                 instance,
                 device,
                 frame: 0,
+                completed_frame: -1,
                 should_recreate: false,
                 settings: *settings,
                 descriptor_counter: DescriptorCounter::default(),
                 descriptor_sets_count: 0,
+                descriptor_contents: HashMap::new(),
+                pending_descriptor_writes: vec![],
+                descriptor_set_layouts: HashMap::new(),
+                descriptor_templates: Default::default(),
+                layout_cache: Default::default(),
                 image_index: 0, // cause just init'ed, no descriptor setup deferred yet
                 // delayed_descriptor_setups: vec![],
                 main_command_buffers: Default::default(),
@@ -429,6 +618,13 @@ impl Renderer {
                 debug_utils_loader,
                 debug_utils_device_loader,
                 push_descriptors_loader,
+                synchronization2_loader,
+                mipmap_pipe: Default::default(),
+                capture_request: None,
+                pending_captures: vec![],
+                capture_writer: Default::default(),
+                staging: Default::default(),
+                frame_wait_semaphores: vec![],
             }
         }
     }
@@ -436,7 +632,7 @@ impl Renderer {
     #[cold]
     #[optimize(size)]
     pub unsafe fn create_instance(
-        window: &Window,
+        window: Option<&Window>,
         entry: &Entry,
         data: &mut VulkanData,
     ) -> Instance {
@@ -489,10 +685,15 @@ impl Renderer {
         }
 
         // Extensions
-        let mut extensions =
-            ash_window::enumerate_required_extensions(window.display_handle().unwrap().as_raw())
-                .unwrap()
-                .to_vec();
+        // headless needs no surface extensions
+        let mut extensions = match window {
+            Some(window) => {
+                ash_window::enumerate_required_extensions(window.display_handle().unwrap().as_raw())
+                    .unwrap()
+                    .to_vec()
+            }
+            None => vec![],
+        };
 
         // Required by Vulkan SDK on macOS since 1.3.216.
         let flags = if cfg!(target_os = "macos")
@@ -541,13 +742,24 @@ impl Renderer {
     }
     /// buffers, images, pipelines - everything created manually should be destroyed manually before this funcall
     pub unsafe fn destroy(mut self) {
+        // not worth leaking every other Vulkan object over
+        for (path, error) in self.finish_captures() {
+            eprintln!("Failed to write capture {}: {}", path.display(), error);
+        }
+        self.destroy_staging();
         self.process_deletion_queues_untill_all_done();
+        self.destroy_mipmap_pipe();
         {
-            self.device.destroy_descriptor_pool(self.vulkan_data.descriptor_pool, None);
-            self.vulkan_data.descriptor_pool = vk::DescriptorPool::null();
+            self.vulkan_data.descriptor_pools.destroy(&self.device);
+            self.destroy_descriptor_templates();
+            self.destroy_layout_cache();
         }
         self.device.destroy_command_pool(self.vulkan_data.command_pool, None);
-        self.destroy_swapchain();
+        self.device.destroy_command_pool(self.vulkan_data.compute_command_pool, None);
+        self.device.destroy_command_pool(self.vulkan_data.transfer_command_pool, None);
+        if !self.vulkan_data.headless {
+            self.destroy_swapchain();
+        }
         self.destroy_sync_primitives();
 
         // i FUCKING HATE that they implement it in a drop
@@ -555,7 +767,7 @@ impl Renderer {
         std::mem::drop(self.allocator);
 
         self.device.destroy_device(None);
-        unsafe {
+        if !self.vulkan_data.headless {
             self.surface_loader.destroy_surface(self.vulkan_data.surface, None);
         }
         self.instance.destroy_instance(None);
@@ -643,31 +855,95 @@ impl Renderer {
     #[cold]
     #[optimize(size)]
     pub fn bind_compute_pipe(&self, cmb: &vk::CommandBuffer, pipe: &ComputePipe) {
+        self.bind_compute_pipe_with_sets(cmb, pipe, 0, &[], &[]);
+    }
+
+    #[cold]
+    #[optimize(size)]
+    pub fn bind_raster_pipe(&self, cmb: &vk::CommandBuffer, pipe: &RasterPipe) {
+        self.bind_raster_pipe_with_sets(cmb, pipe, 0, &[], &[]);
+    }
+
+    // binds pipe, its own set (at pipe.set_index) and sets at first_set..
+    // dynamic_offsets are for dynamic buffers in `sets`, in set and then binding order
+    // if sets cover pipe.set_index, own set is not bound (pass it yourself, with offsets if it has dynamic buffers)
+    #[cold]
+    #[optimize(size)]
+    pub fn bind_compute_pipe_with_sets(
+        &self,
+        cmb: &vk::CommandBuffer,
+        pipe: &ComputePipe,
+        first_set: u32,
+        sets: &[vk::DescriptorSet],
+        dynamic_offsets: &[u32],
+    ) {
         unsafe {
             self.device.cmd_bind_pipeline(*cmb, vk::PipelineBindPoint::COMPUTE, pipe.line);
-            self.device.cmd_bind_descriptor_sets(
-                *cmb,
-                vk::PipelineBindPoint::COMPUTE,
-                pipe.line_layout,
-                0,
-                &[*pipe.sets.current()],
-                &[],
-            );
         }
+        self.bind_own_and_extra_sets(cmb, pipe, &pipe.sets, first_set, sets, dynamic_offsets);
     }
 
     #[cold]
     #[optimize(size)]
-    pub fn bind_raster_pipe(&self, cmb: &vk::CommandBuffer, pipe: &RasterPipe) {
+    pub fn bind_raster_pipe_with_sets(
+        &self,
+        cmb: &vk::CommandBuffer,
+        pipe: &RasterPipe,
+        first_set: u32,
+        sets: &[vk::DescriptorSet],
+        dynamic_offsets: &[u32],
+    ) {
         unsafe {
             self.device.cmd_bind_pipeline(*cmb, vk::PipelineBindPoint::GRAPHICS, pipe.line);
+        }
+        self.bind_own_and_extra_sets(cmb, pipe, &pipe.sets, first_set, sets, dynamic_offsets);
+    }
+
+    fn bind_own_and_extra_sets<P: Pipe>(
+        &self,
+        cmb: &vk::CommandBuffer,
+        pipe: &P,
+        own_sets: &Ring<vk::DescriptorSet>,
+        first_set: u32,
+        sets: &[vk::DescriptorSet],
+        dynamic_offsets: &[u32],
+    ) {
+        let own_covered = (first_set..first_set + sets.len() as u32).contains(&pipe.set_index());
+        // push descriptor pipes have no sets, see cmd_push_descriptors
+        if !own_sets.is_empty() && !own_covered {
+            self.cmd_bind_sets(cmb, pipe, pipe.set_index(), &[*own_sets.current()], &[]);
+        }
+        if !sets.is_empty() {
+            self.cmd_bind_sets(cmb, pipe, first_set, sets, dynamic_offsets);
+        }
+    }
+
+    // vkCmdBindDescriptorSets into pipe's layout (pipe does not have to be bound, layout just has to be compatible)
+    #[optimize(speed)]
+    pub fn cmd_bind_sets<P: Pipe>(
+        &self,
+        cmb: &vk::CommandBuffer,
+        pipe: &P,
+        first_set: u32,
+        sets: &[vk::DescriptorSet],
+        dynamic_offsets: &[u32],
+    ) {
+        if let Some(key) = self.layout_cache.borrow().pipeline_layout_key(pipe.line_layout()) {
+            assert!(
+                (first_set as usize + sets.len()) <= key.set_layouts.len(),
+                "Binding sets {first_set}..{} but pipeline layout has {} sets",
+                first_set as usize + sets.len(),
+                key.set_layouts.len()
+            );
+        }
+        unsafe {
             self.device.cmd_bind_descriptor_sets(
                 *cmb,
-                vk::PipelineBindPoint::GRAPHICS,
-                pipe.line_layout,
-                0,
-                &[*pipe.sets.current()],
-                &[],
+                pipe.bind_point(),
+                pipe.line_layout(),
+                first_set,
+                sets,
+                dynamic_offsets,
             );
         }
     }
@@ -704,19 +980,30 @@ impl Renderer {
         image: &Image,
         old_layout: vk::ImageLayout,
         new_layout: vk::ImageLayout,
+    ) {
+        self.transition_image_layout_single_time_range(
+            image,
+            image.whole_range(),
+            old_layout,
+            new_layout,
+        );
+    }
+
+    #[cold]
+    #[optimize(size)]
+    pub fn transition_image_layout_single_time_range(
+        &self,
+        image: &Image,
+        range: vk::ImageSubresourceRange,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
     ) {
         let command_buffer = self.begin_single_time_command_buffer();
         let barrier = vk::ImageMemoryBarrier {
             old_layout,
             new_layout,
             image: image.image,
-            subresource_range: vk::ImageSubresourceRange {
-                aspect_mask: image.aspect,
-                base_mip_level: 0,
-                level_count: 1,
-                base_array_layer: 0,
-                layer_count: 1,
-            },
+            subresource_range: range,
             src_access_mask: vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
             dst_access_mask: vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
             ..Default::default()
@@ -810,6 +1097,9 @@ impl Renderer {
                 // Destroy the buffer before overwriting
                 let buffer =
                     std::mem::replace(&mut self.buffer_deletion_queue[i].buffer, Buffer::default());
+                for view in &buffer.views {
+                    unsafe { self.device.destroy_buffer_view(*view, None) };
+                }
                 self.allocator.free(buffer.allocation);
                 unsafe { self.device.destroy_buffer(buffer.buffer, None) };
             }
@@ -834,11 +1124,12 @@ impl Renderer {
                 let image = self.image_deletion_queue[i].image;
                 let view = self.image_deletion_queue[i].view;
                 let mip_views = std::mem::take(&mut self.image_deletion_queue[i].mip_views);
+                let views = std::mem::take(&mut self.image_deletion_queue[i].views);
                 let allocation = std::mem::take(&mut self.image_deletion_queue[i].allocation);
                 unsafe {
                     self.allocator.free(allocation);
                     self.device.destroy_image_view(view, None);
-                    for mip_view in mip_views {
+                    for mip_view in mip_views.into_iter().chain(views) {
                         self.device.destroy_image_view(mip_view, None);
                     }
                     self.device.destroy_image(image, None);
@@ -1011,25 +1302,46 @@ pub struct VulkanData {
     pub validation: bool,
     // Surface
     pub surface: vk::SurfaceKHR,
+    pub headless: bool, // no surface and swapchain, see Renderer::create_headless
     // Physical Device / Logical Device
     pub physical_device: vk::PhysicalDevice,
     pub graphics_queue: vk::Queue,
     pub present_queue: vk::Queue,
+    // dedicated queues if device has them, graphics queue otherwise. See queues.rs
+    pub compute_queue: vk::Queue,
+    pub transfer_queue: vk::Queue,
+    pub graphics_family: u32,
+    pub compute_family: u32,
+    pub transfer_family: u32,
     // Swapchain
     pub swapchain_format: vk::Format,
     pub swapchain_extent: vk::Extent2D,
+    pub swapchain_usage: vk::ImageUsageFlags,
     pub swapchain: vk::SwapchainKHR,
     pub swapchain_images: Ring<crate::Image>,
     // pub swapchain_image_views: Ring<vk::ImageView>,
     // Command Pool
     pub command_pool: vk::CommandPool,
+    pub compute_command_pool: vk::CommandPool,
+    pub transfer_command_pool: vk::CommandPool,
     // Sync Objects
     pub image_available_semaphores: Ring<vk::Semaphore>,
     pub render_finished_semaphores: Ring<vk::Semaphore>,
     pub in_flight_fences: Ring<vk::Fence>,
     // pub images_in_flight: Ring<vk::Fence>,
-    // Descriptor pool
-    pub descriptor_pool: vk::DescriptorPool,
+    // Descriptor pools, more are created when they run out
+    pub descriptor_pools: descriptor_pool::DescriptorPoolAllocator,
+    // bufferDeviceAddress is enabled (LumalSettings::buffer_device_address)
+    pub buffer_device_address: bool,
+    // descriptor indexing is enabled (LumalSettings::bindless)
+    pub bindless: bool,
+    // vkCmdPipelineBarrier2 is available (core 1.3 or VK_KHR_synchronization2)
+    pub synchronization2: bool,
+    pub synchronization2_khr: bool,
+    // storage images can be written without format in shader (needed by compute mipmap fallback)
+    pub storage_image_write_without_format: bool,
+    // CUBE_ARRAY views can be created (create_cube_image with cubes > 1)
+    pub image_cube_array: bool,
 }
 
 /// Logs debug messages.
@@ -1089,7 +1401,10 @@ unsafe fn check_physical_device(
     physical_device: vk::PhysicalDevice,
 ) -> VkResult<()> {
     QueueFamilyIndices::get(instance, entry, data, physical_device)?;
-    check_physical_device_extensions(instance, physical_device)?;
+    check_physical_device_extensions(instance, data, physical_device)?;
+    if data.headless {
+        return Ok(());
+    }
     let support = SwapchainSupport::get(instance, entry, data, physical_device)?;
     if support.formats.is_empty() || support.present_modes.is_empty() {
         // return Err(anyhow!(SuitabilityError("Insufficient swapchain support.")));
@@ -1099,11 +1414,21 @@ unsafe fn check_physical_device(
     Ok(())
 }
 
+/// DEVICE_EXTENSIONS without swapchain when headless.
+fn required_device_extensions(data: &VulkanData) -> impl Iterator<Item = &'static CStr> {
+    let headless = data.headless;
+    DEVICE_EXTENSIONS
+        .iter()
+        .copied()
+        .filter(move |ext| !(headless && *ext == vk::KHR_SWAPCHAIN_NAME))
+}
+
 /// Checks that a physical device supports the required device extensions.
 #[cold]
 #[optimize(size)]
 unsafe fn check_physical_device_extensions(
     instance: &Instance,
+    data: &VulkanData,
     physical_device: vk::PhysicalDevice,
 ) -> VkResult<()> {
     let extensions = instance
@@ -1113,7 +1438,7 @@ unsafe fn check_physical_device_extensions(
         .collect::<HashSet<_>>();
 
     // Check if all required extensions are supported
-    for required_ext in DEVICE_EXTENSIONS {
+    for required_ext in required_device_extensions(data) {
         let required_bytes = required_ext.to_bytes();
         let required_len = required_bytes.len();
 
@@ -1156,6 +1481,8 @@ unsafe fn create_logical_device(
     let mut unique_indices = HashSet::new();
     unique_indices.insert(indices.graphics);
     unique_indices.insert(indices.present);
+    unique_indices.insert(indices.compute);
+    unique_indices.insert(indices.transfer);
 
     let queue_priorities = &[1.0];
     let queue_infos = unique_indices
@@ -1174,7 +1501,7 @@ unsafe fn create_logical_device(
         vec![]
     };
 
-    let mut extensions = DEVICE_EXTENSIONS.iter().map(|n| n.as_ptr()).collect::<Vec<_>>();
+    let mut extensions = required_device_extensions(data).map(|n| n.as_ptr()).collect::<Vec<_>>();
 
     // Required by Vulkan SDK on macOS since 1.3.216.
     if cfg!(target_os = "macos")
@@ -1194,6 +1521,17 @@ unsafe fn create_logical_device(
         ..Default::default()
     };
 
+    // optional features, only enabled when supported
+    let supported = instance.get_physical_device_features(data.physical_device);
+    if supported.shader_storage_image_write_without_format == vk::TRUE {
+        features.shader_storage_image_write_without_format = vk::TRUE;
+        data.storage_image_write_without_format = true;
+    }
+    if supported.image_cube_array == vk::TRUE {
+        features.image_cube_array = vk::TRUE;
+        data.image_cube_array = true;
+    }
+
     let mut features11 = vk::PhysicalDeviceVulkan11Features {
         storage_push_constant16: vk::TRUE,
         ..Default::default()
@@ -1208,6 +1546,87 @@ unsafe fn create_logical_device(
 
     features12.p_next = &mut features11 as *mut vk::PhysicalDeviceVulkan11Features as *mut c_void;
 
+    if data.buffer_device_address {
+        let mut query = vk::PhysicalDeviceVulkan12Features::default();
+        let mut query2 = vk::PhysicalDeviceFeatures2 {
+            p_next: &mut query as *mut vk::PhysicalDeviceVulkan12Features as *mut c_void,
+            ..Default::default()
+        };
+        instance.get_physical_device_features2(data.physical_device, &mut query2);
+        assert!(
+            query.buffer_device_address == vk::TRUE,
+            "Buffer device address requested but not supported"
+        );
+        features12.buffer_device_address = vk::TRUE;
+    }
+
+    if data.bindless {
+        let mut query = vk::PhysicalDeviceVulkan12Features::default();
+        let mut query2 = vk::PhysicalDeviceFeatures2 {
+            p_next: &mut query as *mut vk::PhysicalDeviceVulkan12Features as *mut c_void,
+            ..Default::default()
+        };
+        instance.get_physical_device_features2(data.physical_device, &mut query2);
+        assert!(
+            query.descriptor_indexing == vk::TRUE
+                && query.descriptor_binding_partially_bound == vk::TRUE
+                && query.descriptor_binding_variable_descriptor_count == vk::TRUE
+                && query.runtime_descriptor_array == vk::TRUE
+                && query.shader_sampled_image_array_non_uniform_indexing == vk::TRUE
+                && query.shader_storage_image_array_non_uniform_indexing == vk::TRUE
+                && query.shader_storage_buffer_array_non_uniform_indexing == vk::TRUE
+                && query.descriptor_binding_sampled_image_update_after_bind == vk::TRUE
+                && query.descriptor_binding_storage_image_update_after_bind == vk::TRUE
+                && query.descriptor_binding_storage_buffer_update_after_bind == vk::TRUE
+                && query.descriptor_binding_update_unused_while_pending == vk::TRUE,
+            "Bindless requested but descriptor indexing is not (fully) supported"
+        );
+        features12.descriptor_indexing = vk::TRUE;
+        features12.descriptor_binding_partially_bound = vk::TRUE;
+        features12.descriptor_binding_variable_descriptor_count = vk::TRUE;
+        features12.runtime_descriptor_array = vk::TRUE;
+        features12.shader_sampled_image_array_non_uniform_indexing = vk::TRUE;
+        features12.shader_storage_image_array_non_uniform_indexing = vk::TRUE;
+        features12.shader_storage_buffer_array_non_uniform_indexing = vk::TRUE;
+        features12.descriptor_binding_sampled_image_update_after_bind = vk::TRUE;
+        features12.descriptor_binding_storage_image_update_after_bind = vk::TRUE;
+        features12.descriptor_binding_storage_buffer_update_after_bind = vk::TRUE;
+        features12.descriptor_binding_update_unused_while_pending = vk::TRUE;
+    }
+
+    // synchronization2 is optional, barriers fall back to legacy ones without it
+    let mut features_sync2 = vk::PhysicalDeviceSynchronization2Features::default();
+    {
+        let api_version = instance.get_physical_device_properties(data.physical_device).api_version;
+        let has_extension = instance
+            .enumerate_device_extension_properties(data.physical_device)
+            .unwrap()
+            .iter()
+            .any(|e| e.extension_name_as_c_str() == Ok(vk::KHR_SYNCHRONIZATION2_NAME));
+        let core = vk::api_version_minor(api_version) >= 3;
+
+        let mut query = vk::PhysicalDeviceSynchronization2Features::default();
+        let mut query2 = vk::PhysicalDeviceFeatures2 {
+            p_next: &mut query as *mut vk::PhysicalDeviceSynchronization2Features as *mut c_void,
+            ..Default::default()
+        };
+        if core || has_extension {
+            instance.get_physical_device_features2(data.physical_device, &mut query2);
+        }
+
+        if query.synchronization2 == vk::TRUE {
+            data.synchronization2 = true;
+            features_sync2.synchronization2 = vk::TRUE;
+            features11.p_next = &mut features_sync2
+                as *mut vk::PhysicalDeviceSynchronization2Features
+                as *mut c_void;
+            if !core {
+                data.synchronization2_khr = true;
+                extensions.push(vk::KHR_SYNCHRONIZATION2_NAME.as_ptr());
+            }
+        }
+    }
+
     let mut features2 = vk::PhysicalDeviceFeatures2 {
         features,
         p_next: &mut features12 as *mut vk::PhysicalDeviceVulkan12Features as *mut c_void,
@@ -1229,6 +1648,11 @@ unsafe fn create_logical_device(
 
     data.graphics_queue = device.get_device_queue(indices.graphics, 0);
     data.present_queue = device.get_device_queue(indices.present, 0);
+    data.compute_queue = device.get_device_queue(indices.compute, 0);
+    data.transfer_queue = device.get_device_queue(indices.transfer, 0);
+    data.graphics_family = indices.graphics;
+    data.compute_family = indices.compute;
+    data.transfer_family = indices.transfer;
 
     device
 }
@@ -1250,6 +1674,9 @@ unsafe fn create_swapchain(
     let extent = get_swapchain_extent(window, support.capabilities);
     data.swapchain_format = surface_format.format;
     data.swapchain_extent = extent;
+    // TRANSFER_SRC is for frame capture, not every surface supports it
+    data.swapchain_usage = vk::ImageUsageFlags::COLOR_ATTACHMENT
+        | (support.capabilities.supported_usage_flags & vk::ImageUsageFlags::TRANSFER_SRC);
     let max_image_count = if support.capabilities.max_image_count != 0 {
         support.capabilities.max_image_count
     } else {
@@ -1272,7 +1699,7 @@ unsafe fn create_swapchain(
         image_color_space: surface_format.color_space,
         image_extent: extent,
         image_array_layers: 1,
-        image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+        image_usage: data.swapchain_usage,
         image_sharing_mode,
         queue_family_index_count: queue_family_indices.len() as u32,
         p_queue_family_indices: queue_family_indices.as_ptr(),
@@ -1352,12 +1779,19 @@ unsafe fn create_swapchain(
                     mip_views: vec![],
                     format: surface_format.format,
                     aspect: ImageAspectFlags::COLOR,
+                    image_type: vk::ImageType::TYPE_2D,
                     extent: vk::Extent3D {
                         width: extent.width,
                         height: extent.height,
                         depth: 1,
                     },
-                    mip_levels: 0,
+                    mip_levels: 1,
+                    layers: 1,
+                    flags: vk::ImageCreateFlags::empty(),
+                    usage: data.swapchain_usage,
+                    views: vec![],
+                    // swapchain images start in whatever presentation engine gave us
+                    states: vec![ImageState::default()],
                 }
             })
             .collect(),
@@ -1426,12 +1860,20 @@ unsafe fn create_command_pool(
     data: &mut VulkanData,
 ) {
     let indices = QueueFamilyIndices::get(instance, entry, data, data.physical_device).unwrap();
-    let info = vk::CommandPoolCreateInfo {
-        flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
-        queue_family_index: indices.graphics,
-        ..Default::default()
+    let pool_for = |queue_family_index| {
+        let info = vk::CommandPoolCreateInfo {
+            flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            queue_family_index,
+            ..Default::default()
+        };
+        device.create_command_pool(&info, None).unwrap()
     };
-    data.command_pool = device.create_command_pool(&info, None).unwrap();
+    data.command_pool = pool_for(indices.graphics);
+    // separate pools even if families are the same, so command buffers can be recorded on other threads
+    // submitting is another story: without dedicated family compute / transfer queue is the same
+    // VkQueue as graphics, and submits to it from several threads need external synchronization
+    data.compute_command_pool = pool_for(indices.compute);
+    data.transfer_command_pool = pool_for(indices.transfer);
 }
 
 #[cold]
@@ -1458,6 +1900,8 @@ unsafe fn create_sync_objects(device: &Device, data: &mut VulkanData) {
 struct QueueFamilyIndices {
     graphics: u32,
     present: u32,
+    compute: u32,  // async compute family, graphics if there is none
+    transfer: u32, // DMA family, graphics if there is none
 }
 
 impl QueueFamilyIndices {
@@ -1478,19 +1922,43 @@ impl QueueFamilyIndices {
             .map(|i| i as u32);
 
         let mut present = None;
-        for (index, _) in properties.iter().enumerate() {
-            if surface_loader.get_physical_device_surface_support(
-                physical_device,
-                index as u32,
-                data.surface,
-            )? {
-                present = Some(index as u32);
-                break;
+        if data.headless {
+            // nothing to present to, graphics queue is used for everything
+            present = graphics;
+        } else {
+            for (index, _) in properties.iter().enumerate() {
+                if surface_loader.get_physical_device_surface_support(
+                    physical_device,
+                    index as u32,
+                    data.surface,
+                )? {
+                    present = Some(index as u32);
+                    break;
+                }
             }
         }
 
+        // prefer families that can do as little else as possible - those are the dedicated ones
+        let dedicated = |wanted: vk::QueueFlags, unwanted: vk::QueueFlags| {
+            properties
+                .iter()
+                .position(|p| p.queue_flags.contains(wanted) && !p.queue_flags.intersects(unwanted))
+                .map(|i| i as u32)
+        };
+        let compute = dedicated(vk::QueueFlags::COMPUTE, vk::QueueFlags::GRAPHICS);
+        let transfer = dedicated(
+            vk::QueueFlags::TRANSFER,
+            vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE,
+        )
+        .or_else(|| dedicated(vk::QueueFlags::TRANSFER, vk::QueueFlags::GRAPHICS));
+
         if let (Some(graphics), Some(present)) = (graphics, present) {
-            Ok(Self { graphics, present })
+            Ok(Self {
+                graphics,
+                present,
+                compute: compute.unwrap_or(graphics),
+                transfer: transfer.unwrap_or(graphics),
+            })
         } else {
             println!("Missing required queue families");
             exit(1);
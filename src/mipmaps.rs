@@ -0,0 +1,290 @@
+use crate::barriers::ImageUsage;
+use crate::images::mip_extent;
+use crate::{ComputePipe, Image, Renderer};
+use ash::vk;
+
+// see shaders/mipmap.comp
+const MIPMAP_SHADER: &[u8] = include_bytes!("shaders/mipmap.comp.spv");
+
+#[repr(C)]
+struct MipmapPush {
+    src_size: [i32; 2],
+    dst_size: [i32; 2],
+}
+
+impl Renderer {
+    // fills mips 1.. of image from mip 0. Leaves whole image in ShaderRead
+    // blits when format supports it, otherwise downsamples with compute
+    // (needs STORAGE | SAMPLED usage and shaderStorageImageWriteWithoutFormat)
+    // for blits image has to be created with TRANSFER_SRC | TRANSFER_DST usage
+    #[cold]
+    #[optimize(size)]
+    pub fn cmd_generate_mipmaps(
+        &self,
+        cmdbuf: &vk::CommandBuffer,
+        image: &mut Image,
+        filter: vk::Filter,
+    ) {
+        assert!(image.aspect == vk::ImageAspectFlags::COLOR);
+
+        if image.mip_levels > 1 {
+            if self.can_blit_mipmaps(image.format, filter) {
+                self.generate_mipmaps_blit(cmdbuf, image, filter);
+            } else {
+                assert!(
+                    self.can_compute_mipmaps(image.format),
+                    "Format {:?} can neither be blitted nor written as storage image (without format)",
+                    image.format
+                );
+                assert!(
+                    image.usage.contains(vk::ImageUsageFlags::STORAGE),
+                    "Format {:?} can not be blitted, compute mipmaps need image with STORAGE usage",
+                    image.format
+                );
+                // source mip is read as SAMPLED_IMAGE in SHADER_READ_ONLY_OPTIMAL
+                assert!(
+                    image.usage.contains(vk::ImageUsageFlags::SAMPLED),
+                    "Format {:?} can not be blitted, compute mipmaps need image with SAMPLED usage",
+                    image.format
+                );
+                self.generate_mipmaps_compute(cmdbuf, image);
+            }
+        }
+
+        self.transition(cmdbuf, image, ImageUsage::ShaderRead);
+    }
+
+    fn optimal_tiling_features(&self, format: vk::Format) -> vk::FormatFeatureFlags {
+        unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.vulkan_data.physical_device, format)
+        }
+        .optimal_tiling_features
+    }
+
+    // whether cmd_generate_mipmaps takes blit path for format
+    pub(crate) fn can_blit_mipmaps(&self, format: vk::Format, filter: vk::Filter) -> bool {
+        let mut needed = vk::FormatFeatureFlags::BLIT_SRC | vk::FormatFeatureFlags::BLIT_DST;
+        if filter == vk::Filter::LINEAR {
+            needed |= vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+        }
+        self.optimal_tiling_features(format).contains(needed)
+    }
+
+    // whether compute fallback can write format (image still needs STORAGE usage)
+    pub(crate) fn can_compute_mipmaps(&self, format: vk::Format) -> bool {
+        self.optimal_tiling_features(format)
+            .contains(vk::FormatFeatureFlags::STORAGE_IMAGE)
+            && self.vulkan_data.storage_image_write_without_format
+    }
+
+    #[cold]
+    #[optimize(size)]
+    fn generate_mipmaps_blit(
+        &self,
+        cmdbuf: &vk::CommandBuffer,
+        image: &mut Image,
+        filter: vk::Filter,
+    ) {
+        for mip in 1..image.mip_levels {
+            self.transition_range(
+                cmdbuf,
+                image,
+                image.mip_range(mip - 1),
+                ImageUsage::TransferSrc,
+            );
+            self.transition_range(cmdbuf, image, image.mip_range(mip), ImageUsage::TransferDst);
+
+            let src_extent = mip_extent(image.extent, mip - 1);
+            let dst_extent = mip_extent(image.extent, mip);
+
+            let blit_op = vk::ImageBlit {
+                src_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: image.aspect,
+                    mip_level: mip - 1,
+                    base_array_layer: 0,
+                    layer_count: image.layers,
+                },
+                src_offsets: [
+                    vk::Offset3D { x: 0, y: 0, z: 0 },
+                    vk::Offset3D {
+                        x: src_extent.width as i32,
+                        y: src_extent.height as i32,
+                        z: src_extent.depth as i32,
+                    },
+                ],
+                dst_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: image.aspect,
+                    mip_level: mip,
+                    base_array_layer: 0,
+                    layer_count: image.layers,
+                },
+                dst_offsets: [
+                    vk::Offset3D { x: 0, y: 0, z: 0 },
+                    vk::Offset3D {
+                        x: dst_extent.width as i32,
+                        y: dst_extent.height as i32,
+                        z: dst_extent.depth as i32,
+                    },
+                ],
+            };
+
+            unsafe {
+                self.device.cmd_blit_image(
+                    *cmdbuf,
+                    image.image,
+                    vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                    image.image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &[blit_op],
+                    filter,
+                );
+            }
+        }
+    }
+
+    // only handles 2D images and first layer
+    #[cold]
+    #[optimize(size)]
+    fn generate_mipmaps_compute(&self, cmdbuf: &vk::CommandBuffer, image: &mut Image) {
+        assert!(image.extent.depth == 1 && image.layers == 1);
+        let pipe = self.mipmap_pipe();
+
+        unsafe {
+            self.device
+                .cmd_bind_pipeline(*cmdbuf, vk::PipelineBindPoint::COMPUTE, pipe.line);
+        }
+
+        for mip in 1..image.mip_levels {
+            let src_view = self.transition_mip(cmdbuf, image, mip - 1, ImageUsage::ShaderRead);
+            let dst_view = self.transition_mip(cmdbuf, image, mip, ImageUsage::ShaderWrite);
+
+            let src_extent = mip_extent(image.extent, mip - 1);
+            let dst_extent = mip_extent(image.extent, mip);
+
+            let src_info = vk::DescriptorImageInfo {
+                image_view: src_view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                sampler: vk::Sampler::null(),
+            };
+            let dst_info = vk::DescriptorImageInfo {
+                image_view: dst_view,
+                image_layout: vk::ImageLayout::GENERAL,
+                sampler: vk::Sampler::null(),
+            };
+            let writes = [
+                vk::WriteDescriptorSet {
+                    dst_binding: 0,
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+                    p_image_info: &src_info,
+                    ..Default::default()
+                },
+                vk::WriteDescriptorSet {
+                    dst_binding: 1,
+                    descriptor_count: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                    p_image_info: &dst_info,
+                    ..Default::default()
+                },
+            ];
+
+            let push = MipmapPush {
+                src_size: [src_extent.width as i32, src_extent.height as i32],
+                dst_size: [dst_extent.width as i32, dst_extent.height as i32],
+            };
+            let push_bytes = unsafe {
+                std::slice::from_raw_parts(
+                    &push as *const MipmapPush as *const u8,
+                    size_of::<MipmapPush>(),
+                )
+            };
+
+            unsafe {
+                self.push_descriptors_loader.cmd_push_descriptor_set(
+                    *cmdbuf,
+                    vk::PipelineBindPoint::COMPUTE,
+                    pipe.line_layout,
+                    0,
+                    &writes,
+                );
+                self.device.cmd_push_constants(
+                    *cmdbuf,
+                    pipe.line_layout,
+                    vk::ShaderStageFlags::COMPUTE,
+                    0,
+                    push_bytes,
+                );
+                self.device.cmd_dispatch(
+                    *cmdbuf,
+                    dst_extent.width.div_ceil(8),
+                    dst_extent.height.div_ceil(8),
+                    1,
+                );
+            }
+        }
+    }
+
+    // created on first use, most apps never need it
+    #[cold]
+    #[optimize(size)]
+    fn mipmap_pipe(&self) -> &ComputePipe {
+        self.mipmap_pipe.get_or_init(|| {
+            let bindings = [
+                vk::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::COMPUTE,
+                    ..Default::default()
+                },
+                vk::DescriptorSetLayoutBinding {
+                    binding: 1,
+                    descriptor_type: vk::DescriptorType::STORAGE_IMAGE,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::COMPUTE,
+                    ..Default::default()
+                },
+            ];
+            let layout_info = vk::DescriptorSetLayoutCreateInfo {
+                flags: vk::DescriptorSetLayoutCreateFlags::PUSH_DESCRIPTOR_KHR,
+                binding_count: bindings.len() as u32,
+                p_bindings: bindings.as_ptr(),
+                ..Default::default()
+            };
+
+            let mut pipe = ComputePipe {
+                set_layout: unsafe {
+                    self.device.create_descriptor_set_layout(&layout_info, None).unwrap()
+                },
+                ..Default::default()
+            };
+            self.create_compute_pipe(
+                &mut pipe,
+                &[],
+                MIPMAP_SHADER,
+                &[vk::PushConstantRange {
+                    stage_flags: vk::ShaderStageFlags::COMPUTE,
+                    offset: 0,
+                    size: size_of::<MipmapPush>() as u32,
+                }],
+                vk::PipelineCreateFlags::empty(),
+                #[cfg(feature = "debug_validation_names")]
+                Some("Mipmap"),
+            );
+            pipe
+        })
+    }
+
+    #[cold]
+    #[optimize(size)]
+    pub(crate) fn destroy_mipmap_pipe(&mut self) {
+        if let Some(pipe) = self.mipmap_pipe.take() {
+            unsafe {
+                self.device.destroy_pipeline(pipe.line, None);
+                self.device.destroy_descriptor_set_layout(pipe.set_layout, None);
+            }
+            self.release_pipeline_layout(pipe.line_layout);
+        }
+    }
+}
@@ -8,6 +8,44 @@ use std::{error, ffi::CStr, ptr::slice_from_raw_parts};
 
 // const PREFIXES: &[&str] = &["../shaders/", "../../shaders/", "shaders/compiled/"];
 
+// actual layouts of pipeline layout and index of pipe's own set in them
+// empty set_layouts is just own set at 0
+#[cold]
+#[optimize(size)]
+fn resolve_pipe_set_layouts(
+    own_layout: vk::DescriptorSetLayout,
+    set_layouts: &[PipeSetLayout],
+) -> (Vec<vk::DescriptorSetLayout>, u32) {
+    if set_layouts.is_empty() {
+        return (vec![own_layout], 0);
+    }
+    let own_count = set_layouts.iter().filter(|set| **set == PipeSetLayout::Own).count();
+    assert!(
+        own_count == 1,
+        "Pipe set layouts have to contain PipeSetLayout::Own exactly once, got {own_count}"
+    );
+
+    let mut set_index = 0;
+    let layouts = set_layouts
+        .iter()
+        .enumerate()
+        .map(|(i, set)| match set {
+            PipeSetLayout::Own => {
+                set_index = i as u32;
+                own_layout
+            }
+            PipeSetLayout::External(layout) => {
+                assert!(
+                    *layout != vk::DescriptorSetLayout::null(),
+                    "External set {i} is null"
+                );
+                *layout
+            }
+        })
+        .collect();
+    (layouts, set_index)
+}
+
 impl Renderer {
     #[cold]
     #[optimize(size)]
@@ -17,18 +55,18 @@ impl Renderer {
         assert!(pipe.set_layout != vk::DescriptorSetLayout::null());
         unsafe {
             self.device.destroy_pipeline(pipe.line, None);
-            self.device.destroy_pipeline_layout(pipe.line_layout, None);
-            self.device.destroy_descriptor_set_layout(pipe.set_layout, None);
-            self.device
-                .free_descriptor_sets(self.vulkan_data.descriptor_pool, pipe.sets.as_slice())
-                .unwrap();
         }
+        // layouts are shared, destroyed when last user releases them
+        self.release_pipeline_layout(pipe.line_layout);
+        self.release_descriptor_set_layout(pipe.set_layout);
+        self.free_descriptor_sets(pipe.sets.as_slice());
         // reset the whole thing. Its like raii but explicit
         *pipe = ComputePipe {
             line: vk::Pipeline::null(),
             line_layout: vk::PipelineLayout::null(),
             sets: Ring::new(0),
             set_layout: vk::DescriptorSetLayout::null(),
+            set_index: 0,
         };
     }
     #[cold]
@@ -39,12 +77,11 @@ impl Renderer {
         assert!(pipe.set_layout != vk::DescriptorSetLayout::null());
         unsafe {
             self.device.destroy_pipeline(pipe.line, None);
-            self.device.destroy_pipeline_layout(pipe.line_layout, None);
-            self.device.destroy_descriptor_set_layout(pipe.set_layout, None);
-            self.device
-                .free_descriptor_sets(self.vulkan_data.descriptor_pool, pipe.sets.as_slice())
-                .unwrap();
         }
+        // layouts are shared, destroyed when last user releases them
+        self.release_pipeline_layout(pipe.line_layout);
+        self.release_descriptor_set_layout(pipe.set_layout);
+        self.free_descriptor_sets(pipe.sets.as_slice());
         // reset the whole thing. Its like raii but explicit
         // *pipe = RasterPipe {
         //     line: vk::Pipeline::null(),
@@ -56,15 +93,56 @@ impl Renderer {
         // };
     }
 
+    // push ranges follow vulkan rules - offsets / sizes multiple of 4, within device limit,
+    // every stage in at most one range. stages have to be in allowed_stages
+    #[cold]
+    #[optimize(size)]
+    fn validate_push_ranges(
+        &self,
+        push_ranges: &[vk::PushConstantRange],
+        allowed_stages: vk::ShaderStageFlags,
+    ) {
+        let max_size = unsafe {
+            self.instance.get_physical_device_properties(self.vulkan_data.physical_device)
+        }
+        .limits
+        .max_push_constants_size;
+
+        let mut used_stages = vk::ShaderStageFlags::empty();
+        for (i, range) in push_ranges.iter().enumerate() {
+            assert!(range.size > 0, "Push range {i} is empty");
+            assert!(
+                range.offset % 4 == 0 && range.size % 4 == 0,
+                "Push range {i} offset and size have to be multiple of 4"
+            );
+            assert!(
+                range.offset + range.size <= max_size,
+                "Push range {i} ends at {}, device limit is {max_size}",
+                range.offset + range.size
+            );
+            assert!(
+                allowed_stages.contains(range.stage_flags) && !range.stage_flags.is_empty(),
+                "Push range {i} stages {:?} are not in pipe stages {allowed_stages:?}",
+                range.stage_flags
+            );
+            assert!(
+                !used_stages.intersects(range.stage_flags),
+                "Push range {i} stages {:?} are already in other range",
+                range.stage_flags
+            );
+            used_stages |= range.stage_flags;
+        }
+    }
+
     #[cold]
     #[optimize(size)]
-    // descriptors in extra_dynamic_layout are going to set 1, all others are going to set 0
+    // set_layouts is whole pipeline layout in set order, pipe's own sets go to PipeSetLayout::Own index
     pub fn create_compute_pipe(
         &self,
         pipe: &mut ComputePipe,
-        extra_dynamic_layout: Option<vk::DescriptorSetLayout>,
+        set_layouts: &[PipeSetLayout],
         spirv_code: &[u8],
-        push_size: u32,
+        push_ranges: &[vk::PushConstantRange],
         create_flags: vk::PipelineCreateFlags,
         #[cfg(feature = "debug_validation_names")] debug_name: Option<&str>,
     ) {
@@ -102,33 +180,11 @@ impl Renderer {
         // };
 
         // Descriptor set layouts
-        let mut used_dset_layouts = vec![pipe.set_layout];
-        if let Some(dynamic_layout) = extra_dynamic_layout {
-            used_dset_layouts.push(dynamic_layout);
-        }
+        let (used_dset_layouts, set_index) = resolve_pipe_set_layouts(pipe.set_layout, set_layouts);
+        self.validate_push_ranges(push_ranges, vk::ShaderStageFlags::COMPUTE);
 
-        // Pipeline layout
-        let pipeline_layout_info = vk::PipelineLayoutCreateInfo {
-            set_layout_count: used_dset_layouts.len() as u32,
-            p_set_layouts: used_dset_layouts.as_ptr(),
-            push_constant_range_count: (push_size > 0) as u32,
-            p_push_constant_ranges: if (push_size > 0) {
-                &vk::PushConstantRange {
-                    stage_flags: vk::ShaderStageFlags::COMPUTE,
-                    offset: 0,
-                    size: push_size,
-                }
-            } else {
-                std::ptr::null()
-            },
-            ..Default::default()
-        };
-
-        let line_layout = unsafe {
-            self.device
-                .create_pipeline_layout(&pipeline_layout_info, None)
-                .expect("Failed to create pipeline layout")
-        };
+        // Pipeline layout (cached, pipes with same layouts share it)
+        let line_layout = self.acquire_pipeline_layout(&used_dset_layouts, push_ranges);
 
         // Compute pipeline
         let pipeline_info = vk::ComputePipelineCreateInfo {
@@ -155,6 +211,7 @@ impl Renderer {
         // Update the pipeline
         pipe.line = line;
         pipe.line_layout = line_layout;
+        pipe.set_index = set_index;
 
         set_debug_names!(
             self,
@@ -166,11 +223,12 @@ impl Renderer {
 
     #[cold]
     #[optimize(size)]
-    // descriptors in extra_dynamic_layout are going to set 1, all others are going to set 0
+    // set_layouts is whole pipeline layout in set order, pipe's own sets go to PipeSetLayout::Own index
+    // push ranges stages have to be subset of shader_stages
     pub fn create_raster_pipe(
         &self,
         pipe: &mut RasterPipe,
-        extra_dynamic_layout: Option<vk::DescriptorSetLayout>,
+        set_layouts: &[PipeSetLayout],
         shader_stages: &[ShaderStage],
         attr_desc: &[AttrFormOffs],
         stride: u32,
@@ -178,7 +236,7 @@ impl Renderer {
         topology: vk::PrimitiveTopology,
         extent: vk::Extent2D,
         blends: &[BlendAttachment],
-        push_size: u32,
+        push_ranges: &[vk::PushConstantRange],
         depth_test: DepthTesting,
         depth_compare_op: vk::CompareOp,
         culling: vk::CullModeFlags,
@@ -280,36 +338,16 @@ impl Renderer {
             ..Default::default()
         };
 
-        let used_dset_layouts: &[vk::DescriptorSetLayout] = match extra_dynamic_layout {
-            Some(layout) => &[pipe.set_layout, layout],
-            None => &[pipe.set_layout],
-        };
+        let (used_dset_layouts, set_index) = resolve_pipe_set_layouts(pipe.set_layout, set_layouts);
 
-        let mut push_range = vk::PushConstantRange {
-            stage_flags: vk::ShaderStageFlags::empty(),
-            offset: 0,
-            size: push_size,
-        };
+        let mut all_stages = vk::ShaderStageFlags::empty();
         for shader_stage in shader_stages {
-            push_range.stage_flags |= shader_stage.stage;
+            all_stages |= shader_stage.stage;
         }
+        self.validate_push_ranges(push_ranges, all_stages);
 
-        // Setup pipeline layout
-        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo {
-            set_layout_count: used_dset_layouts.len() as u32,
-            p_set_layouts: used_dset_layouts.as_ptr(),
-            push_constant_range_count: (push_size > 0) as u32,
-            p_push_constant_ranges: if (push_size > 0) {
-                &push_range
-            } else {
-                std::ptr::null()
-            },
-            ..Default::default()
-        };
-
-        let pipeline_layout = unsafe {
-            self.device.create_pipeline_layout(&pipeline_layout_create_info, None).unwrap()
-        };
+        // Setup pipeline layout (cached, pipes with same layouts share it)
+        let pipeline_layout = self.acquire_pipeline_layout(&used_dset_layouts, push_ranges);
 
         let binding_description = vk::VertexInputBindingDescription {
             binding: 0,
@@ -436,6 +474,7 @@ impl Renderer {
         // dots never meant anything]
         pipe.line = pipeline;
         pipe.line_layout = pipeline_layout;
+        pipe.set_index = set_index;
 
         // give debug names to vulkan objects
         set_debug_names!(
@@ -0,0 +1,265 @@
+// dedicated compute / transfer queues, cross-queue submission and ownership transfers
+// on devices without dedicated families everything maps to graphics queue and still works
+// (then it is literally the same VkQueue, so submits to it from several threads need your own lock)
+
+use crate::barriers::{BarrierBatch, ImageState, ImageUsage};
+use crate::{Buffer, Image, Renderer};
+use ash::vk;
+
+// tracked states of whole image as (range, state), consecutive mips of a layer in same state merged
+fn image_state_runs(image: &Image) -> Vec<(vk::ImageSubresourceRange, ImageState)> {
+    let mut runs = vec![];
+    for layer in 0..image.layers {
+        let mut base_mip = 0;
+        for mip in 1..=image.mip_levels {
+            let state = image.state(base_mip, layer);
+            if mip < image.mip_levels && image.state(mip, layer) == state {
+                continue;
+            }
+            runs.push((
+                image.subresource_range(base_mip, mip - base_mip, layer, 1),
+                state,
+            ));
+            base_mip = mip;
+        }
+    }
+    runs
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueueType {
+    Graphics,
+    Compute,
+    Transfer,
+}
+
+impl Renderer {
+    pub fn queue(&self, queue_type: QueueType) -> vk::Queue {
+        match queue_type {
+            QueueType::Graphics => self.vulkan_data.graphics_queue,
+            QueueType::Compute => self.vulkan_data.compute_queue,
+            QueueType::Transfer => self.vulkan_data.transfer_queue,
+        }
+    }
+
+    pub fn queue_family(&self, queue_type: QueueType) -> u32 {
+        match queue_type {
+            QueueType::Graphics => self.vulkan_data.graphics_family,
+            QueueType::Compute => self.vulkan_data.compute_family,
+            QueueType::Transfer => self.vulkan_data.transfer_family,
+        }
+    }
+
+    pub fn command_pool(&self, queue_type: QueueType) -> vk::CommandPool {
+        match queue_type {
+            QueueType::Graphics => self.vulkan_data.command_pool,
+            QueueType::Compute => self.vulkan_data.compute_command_pool,
+            QueueType::Transfer => self.vulkan_data.transfer_command_pool,
+        }
+    }
+
+    // false if queue_type is just graphics queue in disguise
+    pub fn has_dedicated_queue(&self, queue_type: QueueType) -> bool {
+        self.queue_family(queue_type) != self.vulkan_data.graphics_family
+    }
+
+    #[cold]
+    #[optimize(size)]
+    pub fn allocate_command_buffers(
+        &self,
+        queue_type: QueueType,
+        count: u32,
+    ) -> Vec<vk::CommandBuffer> {
+        let alloc_info = vk::CommandBufferAllocateInfo {
+            level: vk::CommandBufferLevel::PRIMARY,
+            command_pool: self.command_pool(queue_type),
+            command_buffer_count: count,
+            ..Default::default()
+        };
+        unsafe { self.device.allocate_command_buffers(&alloc_info).unwrap() }
+    }
+
+    #[cold]
+    #[optimize(size)]
+    pub fn create_semaphore(&self) -> vk::Semaphore {
+        unsafe { self.device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None).unwrap() }
+    }
+
+    #[cold]
+    #[optimize(size)]
+    pub fn destroy_semaphore(&self, semaphore: vk::Semaphore) {
+        unsafe { self.device.destroy_semaphore(semaphore, None) };
+    }
+
+    // submits already ended command buffers to given queue
+    // waits are (semaphore, stage that waits), fence can be null
+    #[cold]
+    #[optimize(speed)]
+    pub fn submit(
+        &self,
+        queue_type: QueueType,
+        command_buffers: &[vk::CommandBuffer],
+        waits: &[(vk::Semaphore, vk::PipelineStageFlags)],
+        signals: &[vk::Semaphore],
+        fence: vk::Fence,
+    ) {
+        let (wait_semaphores, wait_stages): (Vec<_>, Vec<_>) = waits.iter().copied().unzip();
+        let submit_info = vk::SubmitInfo {
+            wait_semaphore_count: wait_semaphores.len() as u32,
+            p_wait_semaphores: wait_semaphores.as_ptr(),
+            p_wait_dst_stage_mask: wait_stages.as_ptr(),
+            command_buffer_count: command_buffers.len() as u32,
+            p_command_buffers: command_buffers.as_ptr(),
+            signal_semaphore_count: signals.len() as u32,
+            p_signal_semaphores: signals.as_ptr(),
+            ..Default::default()
+        };
+        unsafe {
+            self.device.queue_submit(self.queue(queue_type), &[submit_info], fence).unwrap();
+        }
+    }
+
+    // makes next end_frame submission wait for semaphore (signaled by compute / transfer submit)
+    pub fn wait_in_frame(&mut self, semaphore: vk::Semaphore, stage: vk::PipelineStageFlags) {
+        self.frame_wait_semaphores.push((semaphore, stage));
+    }
+
+    // ownership transfer is two barriers: release recorded on "from" queue, acquire on "to" queue,
+    // with semaphore between submissions. When families are the same, release does nothing
+    // and acquire is plain barrier
+
+    #[cold]
+    #[optimize(speed)]
+    pub fn cmd_release_buffer(
+        &self,
+        cmdbuf: &vk::CommandBuffer,
+        buffer: &Buffer,
+        from: QueueType,
+        to: QueueType,
+        src_stage_mask: vk::PipelineStageFlags2,
+        src_access_mask: vk::AccessFlags2,
+    ) {
+        let (src_family, dst_family) = (self.queue_family(from), self.queue_family(to));
+        if src_family == dst_family {
+            return;
+        }
+        let mut batch = BarrierBatch::new();
+        batch.push_buffer(vk::BufferMemoryBarrier2 {
+            src_stage_mask,
+            src_access_mask,
+            src_queue_family_index: src_family,
+            dst_queue_family_index: dst_family,
+            buffer: buffer.buffer,
+            offset: 0,
+            size: vk::WHOLE_SIZE,
+            ..Default::default()
+        });
+        self.cmd_flush_barriers(cmdbuf, &mut batch);
+    }
+
+    #[cold]
+    #[optimize(speed)]
+    pub fn cmd_acquire_buffer(
+        &self,
+        cmdbuf: &vk::CommandBuffer,
+        buffer: &Buffer,
+        from: QueueType,
+        to: QueueType,
+        dst_stage_mask: vk::PipelineStageFlags2,
+        dst_access_mask: vk::AccessFlags2,
+    ) {
+        let (src_family, dst_family) = (self.queue_family(from), self.queue_family(to));
+        let mut batch = BarrierBatch::new();
+        if src_family == dst_family {
+            batch.buffer(
+                buffer,
+                vk::PipelineStageFlags2::ALL_COMMANDS,
+                vk::AccessFlags2::MEMORY_WRITE,
+                dst_stage_mask,
+                dst_access_mask,
+            );
+        } else {
+            batch.push_buffer(vk::BufferMemoryBarrier2 {
+                dst_stage_mask,
+                dst_access_mask,
+                src_queue_family_index: src_family,
+                dst_queue_family_index: dst_family,
+                buffer: buffer.buffer,
+                offset: 0,
+                size: vk::WHOLE_SIZE,
+                ..Default::default()
+            });
+        }
+        self.cmd_flush_barriers(cmdbuf, &mut batch);
+    }
+
+    // releases whole image, transitioning it to layout of usage on the way
+    // does not touch tracked state - acquire needs old layouts too and updates them
+    // every subresource is released from its own tracked state
+    #[cold]
+    #[optimize(speed)]
+    pub fn cmd_release_image(
+        &self,
+        cmdbuf: &vk::CommandBuffer,
+        image: &Image,
+        from: QueueType,
+        to: QueueType,
+        usage: ImageUsage,
+    ) {
+        let (src_family, dst_family) = (self.queue_family(from), self.queue_family(to));
+        if src_family == dst_family {
+            return;
+        }
+        let mut batch = BarrierBatch::new();
+        for (range, old) in image_state_runs(image) {
+            batch.push_image(vk::ImageMemoryBarrier2 {
+                src_stage_mask: old.stage,
+                src_access_mask: old.access,
+                old_layout: old.layout,
+                new_layout: usage.state().layout,
+                src_queue_family_index: src_family,
+                dst_queue_family_index: dst_family,
+                image: image.image,
+                subresource_range: range,
+                ..Default::default()
+            });
+        }
+        self.cmd_flush_barriers(cmdbuf, &mut batch);
+    }
+
+    // acquires whole image released with cmd_release_image (same usage) and tracks new state
+    #[cold]
+    #[optimize(speed)]
+    pub fn cmd_acquire_image(
+        &self,
+        cmdbuf: &vk::CommandBuffer,
+        image: &mut Image,
+        from: QueueType,
+        to: QueueType,
+        usage: ImageUsage,
+    ) {
+        let (src_family, dst_family) = (self.queue_family(from), self.queue_family(to));
+        if src_family == dst_family {
+            self.transition(cmdbuf, image, usage);
+            return;
+        }
+        let new = usage.state();
+        let mut batch = BarrierBatch::new();
+        // has to match release barriers exactly, so same ranges and old layouts
+        for (range, old) in image_state_runs(image) {
+            batch.push_image(vk::ImageMemoryBarrier2 {
+                dst_stage_mask: new.stage,
+                dst_access_mask: new.access,
+                old_layout: old.layout,
+                new_layout: new.layout,
+                src_queue_family_index: src_family,
+                dst_queue_family_index: dst_family,
+                image: image.image,
+                subresource_range: range,
+                ..Default::default()
+            });
+        }
+        self.cmd_flush_barriers(cmdbuf, &mut batch);
+        image.assume_state(new);
+    }
+}
@@ -0,0 +1,251 @@
+// copying data from GPU back to CPU
+// blocking versions wait for queue idle, cmd_ versions record into frame command buffer and
+// return Readback that is ready once that frame's fence has signaled
+
+use std::marker::PhantomData;
+use std::ptr::copy_nonoverlapping;
+
+use crate::barriers::{BarrierBatch, ImageUsage};
+use crate::buffers::Pod;
+use crate::formats::format_aspect_block;
+use crate::{Buffer, Image, MemoryLocation, Renderer};
+use ash::vk;
+
+// pending download. Poll it with is_readback_ready and take data with take_readback
+// dropping it without take_readback leaks staging buffer
+pub struct Readback<T> {
+    pub staging: Buffer,
+    pub count: usize,
+    fence: vk::Fence,
+    frame: i32, // Renderer::frame when copy was recorded
+    _marker: PhantomData<T>,
+}
+
+impl Renderer {
+    // copies whole buffer into CPU memory and waits for it. Slow, but simple
+    #[cold]
+    #[optimize(size)]
+    pub fn download_buffer<T: Pod>(&mut self, buffer: &Buffer) -> Vec<T> {
+        let cmdbuf = self.begin_single_time_command_buffer();
+        let readback = self.cmd_download_buffer::<T>(&cmdbuf, buffer);
+        self.end_single_time_command_buffer(cmdbuf);
+        self.read_and_destroy_staging(readback)
+    }
+
+    // copies one mip of one layer into CPU memory (tightly packed) and waits for it
+    // image is left in TransferSrc
+    #[cold]
+    #[optimize(size)]
+    pub fn download_image(&mut self, image: &mut Image, mip: u32, layer: u32) -> Vec<u8> {
+        let cmdbuf = self.begin_single_time_command_buffer();
+        let readback = self.cmd_download_image(&cmdbuf, image, mip, layer);
+        self.end_single_time_command_buffer(cmdbuf);
+        self.read_and_destroy_staging(readback)
+    }
+
+    // records copy of whole buffer. cmdbuf is expected to be submitted with this frame's fence
+    // (one of the command buffers passed to end_frame)
+    #[cold]
+    #[optimize(size)]
+    pub fn cmd_download_buffer<T: Pod>(
+        &mut self,
+        cmdbuf: &vk::CommandBuffer,
+        buffer: &Buffer,
+    ) -> Readback<T> {
+        assert!(size_of::<T>() != 0);
+        let count = buffer.size as usize / size_of::<T>();
+        let size = count * size_of::<T>();
+        // zero sized VkBuffer is invalid
+        assert!(
+            count != 0,
+            "Buffer of {} bytes is smaller than one {}",
+            buffer.size,
+            std::any::type_name::<T>()
+        );
+
+        let staging = self.create_buffer(
+            vk::BufferUsageFlags::TRANSFER_DST,
+            size,
+            MemoryLocation::GpuToCpu,
+        );
+
+        // we do not know who wrote it last, so wait for everyone
+        let mut batch = BarrierBatch::new();
+        batch.global(
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            vk::AccessFlags2::MEMORY_WRITE,
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_READ,
+        );
+        self.cmd_flush_barriers(cmdbuf, &mut batch);
+
+        unsafe {
+            self.device.cmd_copy_buffer(
+                *cmdbuf,
+                buffer.buffer,
+                staging.buffer,
+                &[vk::BufferCopy {
+                    src_offset: 0,
+                    dst_offset: 0,
+                    size: size as vk::DeviceSize,
+                }],
+            );
+        }
+
+        self.finish_download(cmdbuf, staging, count)
+    }
+
+    // records copy of one mip of one layer, tightly packed
+    // only depth is copied for depth-stencil images
+    #[cold]
+    #[optimize(size)]
+    pub fn cmd_download_image(
+        &mut self,
+        cmdbuf: &vk::CommandBuffer,
+        image: &mut Image,
+        mip: u32,
+        layer: u32,
+    ) -> Readback<u8> {
+        assert!(mip < image.mip_levels && layer < image.layers);
+        let range = image.subresource_range(mip, 1, layer, 1);
+        self.transition_range(cmdbuf, image, range, ImageUsage::TransferSrc);
+        // depth and stencil have to be copied separately, we only download depth
+        let aspect_mask = if image.aspect.contains(vk::ImageAspectFlags::DEPTH) {
+            vk::ImageAspectFlags::DEPTH
+        } else {
+            image.aspect
+        };
+        self.cmd_copy_image_to_readback(
+            cmdbuf,
+            image.image,
+            image.format,
+            image.extent,
+            vk::ImageSubresourceLayers {
+                aspect_mask,
+                mip_level: mip,
+                base_array_layer: layer,
+                layer_count: 1,
+            },
+        )
+    }
+
+    // same as cmd_download_image, but image is expected to already be in TRANSFER_SRC_OPTIMAL
+    // raw handle, so it works for swapchain images too (they are not tracked by us)
+    // subresource has to be single layer of single aspect
+    #[cold]
+    #[optimize(size)]
+    pub(crate) fn cmd_copy_image_to_readback(
+        &mut self,
+        cmdbuf: &vk::CommandBuffer,
+        image: vk::Image,
+        format: vk::Format,
+        image_extent: vk::Extent3D,
+        subresource: vk::ImageSubresourceLayers,
+    ) -> Readback<u8> {
+        assert!(subresource.layer_count == 1);
+        let mip = subresource.mip_level;
+        let block = format_aspect_block(format, subresource.aspect_mask)
+            .unwrap_or_else(|| panic!("Unknown texel size of format {:?}", format));
+
+        let extent = vk::Extent3D {
+            width: (image_extent.width >> mip).max(1),
+            height: (image_extent.height >> mip).max(1),
+            depth: (image_extent.depth >> mip).max(1),
+        };
+        let size = block.slice_size(extent.width, extent.height) * extent.depth as usize;
+
+        let staging = self.create_buffer(
+            vk::BufferUsageFlags::TRANSFER_DST,
+            size,
+            MemoryLocation::GpuToCpu,
+        );
+
+        unsafe {
+            self.device.cmd_copy_image_to_buffer(
+                *cmdbuf,
+                image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                staging.buffer,
+                &[vk::BufferImageCopy {
+                    buffer_offset: 0,
+                    buffer_row_length: 0, // tightly packed
+                    buffer_image_height: 0,
+                    image_subresource: subresource,
+                    image_offset: vk::Offset3D::default(),
+                    image_extent: extent,
+                }],
+            );
+        }
+
+        self.finish_download(cmdbuf, staging, size)
+    }
+
+    // true once GPU is done with copy. Only meaningful after frame with copy was submitted
+    #[cold]
+    #[optimize(speed)]
+    pub fn is_readback_ready<T>(&self, readback: &Readback<T>) -> bool {
+        if self.frame <= readback.frame {
+            // not even submitted yet
+            return false;
+        }
+        // fence was already waited (and reset for reuse) in start_frame, so status says nothing anymore
+        if readback.frame <= self.completed_frame {
+            return true;
+        }
+        // otherwise fence still belongs to readback's frame
+        unsafe { self.device.get_fence_status(readback.fence) }.unwrap_or(false)
+    }
+
+    // takes downloaded data and frees staging buffer. Panics if not ready yet
+    #[cold]
+    #[optimize(size)]
+    pub fn take_readback<T: Pod>(&mut self, readback: Readback<T>) -> Vec<T> {
+        assert!(
+            self.is_readback_ready(&readback),
+            "Readback is not ready yet"
+        );
+        self.read_and_destroy_staging(readback)
+    }
+
+    fn finish_download<T>(
+        &mut self,
+        cmdbuf: &vk::CommandBuffer,
+        staging: Buffer,
+        count: usize,
+    ) -> Readback<T> {
+        // make transfer writes visible to host once fence is waited on
+        let mut batch = BarrierBatch::new();
+        batch.buffer(
+            &staging,
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_WRITE,
+            vk::PipelineStageFlags2::HOST,
+            vk::AccessFlags2::HOST_READ,
+        );
+        self.cmd_flush_barriers(cmdbuf, &mut batch);
+
+        Readback {
+            staging,
+            count,
+            fence: *self.vulkan_data.in_flight_fences.current(),
+            frame: self.frame,
+            _marker: PhantomData,
+        }
+    }
+
+    pub(crate) fn read_and_destroy_staging<T: Pod>(&mut self, readback: Readback<T>) -> Vec<T> {
+        let mut data = Vec::<T>::with_capacity(readback.count);
+        // allocator only hands out coherent host memory, no invalidate needed
+        let mapped = readback.staging.allocation.mapped_ptr().unwrap().as_ptr() as *const u8;
+        unsafe {
+            copy_nonoverlapping(
+                mapped,
+                data.as_mut_ptr() as *mut u8,
+                readback.count * size_of::<T>(),
+            );
+            data.set_len(readback.count);
+        }
+        self.destroy_buffer(readback.staging);
+        data
+    }
+}
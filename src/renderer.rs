@@ -20,6 +20,12 @@ impl Renderer {
             );
             self.device.reset_fences(&[*self.vulkan_data.in_flight_fences.current()]);
         };
+        // fence was last used by frame that is MAX_FRAMES_IN_FLIGHT behind this one
+        self.completed_frame = self.frame - MAX_FRAMES_IN_FLIGHT as i32;
+
+        // everything that waited for this fence is free to reuse now
+        self.reset_staging();
+        self.apply_pending_descriptor_writes();
 
         let begin_info = vk::CommandBufferBeginInfo::default();
 
@@ -35,6 +41,11 @@ impl Renderer {
             }
         }
 
+        // headless has no swapchain to acquire from
+        if self.vulkan_data.headless {
+            return;
+        }
+
         let index_code = unsafe {
             // this is index of swapchain image that we should render to
             // it is not just incremented-wrapped because driver might (and will) juggle them around for perfomance reasons
@@ -75,14 +86,22 @@ impl Renderer {
     #[cold]
     #[optimize(speed)]
     pub fn end_frame(&mut self, command_buffers: &[vk::CommandBuffer], window: &Window) {
+        // last command buffer is the one that renders into swapchain image
+        if let Some(last) = command_buffers.last() {
+            self.record_frame_capture(last);
+        }
         for command_buffer in command_buffers {
             unsafe {
                 self.device.end_command_buffer(*command_buffer).unwrap();
             }
         }
         let signal_semaphores = [*self.vulkan_data.render_finished_semaphores.current()];
-        let wait_semaphores = [*self.vulkan_data.image_available_semaphores.current()];
-        let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let mut wait_semaphores = vec![*self.vulkan_data.image_available_semaphores.current()];
+        let mut wait_stages = vec![vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        for (semaphore, stage) in self.frame_wait_semaphores.drain(..) {
+            wait_semaphores.push(semaphore);
+            wait_stages.push(stage);
+        }
         let submit_info = vk::SubmitInfo {
             wait_semaphore_count: wait_semaphores.len() as u32,
             p_wait_semaphores: wait_semaphores.as_ptr(),
@@ -112,6 +131,47 @@ impl Renderer {
         self.vulkan_data.in_flight_fences.move_next();
         // counter for internal purposes
         self.frame += 1;
+
+        self.write_ready_captures();
+    }
+
+    // end_frame for headless renderer. Nothing to wait on and nothing to present,
+    // command buffers are just submitted with this frame's fence
+    #[cold]
+    #[optimize(speed)]
+    pub fn end_headless_frame(&mut self, command_buffers: &[vk::CommandBuffer]) {
+        for command_buffer in command_buffers {
+            unsafe {
+                self.device.end_command_buffer(*command_buffer).unwrap();
+            }
+        }
+        let (wait_semaphores, wait_stages): (Vec<_>, Vec<_>) =
+            self.frame_wait_semaphores.drain(..).unzip();
+        let submit_info = vk::SubmitInfo {
+            wait_semaphore_count: wait_semaphores.len() as u32,
+            p_wait_semaphores: wait_semaphores.as_ptr(),
+            p_wait_dst_stage_mask: wait_stages.as_ptr(),
+            command_buffer_count: command_buffers.len() as u32,
+            p_command_buffers: command_buffers.as_ptr(),
+            ..Default::default()
+        };
+
+        unsafe {
+            self.device
+                .queue_submit(
+                    self.vulkan_data.graphics_queue,
+                    &[submit_info],
+                    *self.vulkan_data.in_flight_fences.current(),
+                )
+                .unwrap();
+        }
+
+        self.vulkan_data.image_available_semaphores.move_next();
+        self.vulkan_data.render_finished_semaphores.move_next();
+        self.vulkan_data.in_flight_fences.move_next();
+        self.frame += 1;
+
+        self.write_ready_captures();
     }
 
     // figure out if entire thing has to be recreated or not. Does not reacreate, only "flags" it
@@ -0,0 +1,192 @@
+// per-frame linear allocator of staging memory. Every frame in flight has its own chunk,
+// which is bump-allocated from and reset once fence of frame that used it last is waited on
+// (in start_frame). Uploads that do not fit get their own buffer, freed once their frame is done
+// blocking uploads (create_and_upload_*, upload_image_region) do not touch the arena at all, they get
+// dedicated buffers destroyed right after the wait, so uploading outside of frame loop does not pile up
+
+use std::ptr::copy_nonoverlapping;
+
+use crate::barriers::BarrierBatch;
+use crate::ring::Ring;
+use crate::{Buffer, MemoryLocation, Renderer, MAX_FRAMES_IN_FLIGHT};
+use ash::vk;
+
+pub const STAGING_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Default)]
+pub struct StagingArena {
+    pub chunks: Ring<Buffer>,             // created on first use
+    pub offset: usize,                    // first free byte in current chunk
+    pub overflow: Vec<(Buffer, i32)>, // did not fit into chunk, with Renderer::frame they were used in
+    pub single_time: Option<Vec<Buffer>>, // Some while blocking upload is recorded
+}
+
+impl Renderer {
+    // copies data into staging memory and returns where it is. Valid until this frame's fence signals
+    // alignment is in bytes
+    #[cold]
+    #[optimize(speed)]
+    pub fn stage<T>(&mut self, data: &[T], alignment: usize) -> (vk::Buffer, vk::DeviceSize) {
+        let size = size_of_val(data);
+        let (buffer, offset, mapped) = self.staging_alloc(size, alignment);
+        unsafe { copy_nonoverlapping(data.as_ptr() as *const u8, mapped, size) };
+        (buffer, offset)
+    }
+
+    // records copy of data into dst at dst_offset (in bytes)
+    // barriers around it wait for previous users of dst and make data visible to everything that comes next
+    #[cold]
+    #[optimize(speed)]
+    pub fn cmd_upload_buffer<T>(
+        &mut self,
+        cmdbuf: &vk::CommandBuffer,
+        dst: &Buffer,
+        dst_offset: vk::DeviceSize,
+        data: &[T],
+    ) {
+        let size = size_of_val(data) as vk::DeviceSize;
+        if size == 0 {
+            return;
+        }
+        assert!(
+            dst_offset + size <= dst.size,
+            "Upload of {} bytes at {} does not fit into buffer of {}",
+            size,
+            dst_offset,
+            dst.size
+        );
+
+        let (staging, staging_offset) = self.stage(data, 16);
+
+        let mut batch = BarrierBatch::new();
+        batch.buffer(
+            dst,
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE,
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_WRITE,
+        );
+        self.cmd_flush_barriers(cmdbuf, &mut batch);
+
+        unsafe {
+            self.device.cmd_copy_buffer(
+                *cmdbuf,
+                staging,
+                dst.buffer,
+                &[vk::BufferCopy {
+                    src_offset: staging_offset,
+                    dst_offset,
+                    size,
+                }],
+            );
+        }
+
+        batch.buffer(
+            dst,
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_WRITE,
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE,
+        );
+        self.cmd_flush_barriers(cmdbuf, &mut batch);
+    }
+
+    // returns buffer, offset in it and mapped pointer to that offset
+    fn staging_alloc(
+        &mut self,
+        size: usize,
+        alignment: usize,
+    ) -> (vk::Buffer, vk::DeviceSize, *mut u8) {
+        if self.staging.single_time.is_some() {
+            let buffer = self.create_staging_buffer(size);
+            let result = (
+                buffer.buffer,
+                0,
+                buffer.allocation.mapped_ptr().unwrap().as_ptr() as *mut u8,
+            );
+            self.staging.single_time.as_mut().unwrap().push(buffer);
+            return result;
+        }
+
+        if self.staging.chunks.is_empty() {
+            self.staging.chunks = self.create_buffer_rings(
+                MAX_FRAMES_IN_FLIGHT,
+                vk::BufferUsageFlags::TRANSFER_SRC,
+                STAGING_CHUNK_SIZE,
+                MemoryLocation::CpuToGpu,
+            );
+            self.staging.offset = 0;
+        }
+
+        let offset = self.staging.offset.next_multiple_of(alignment);
+        if offset + size <= STAGING_CHUNK_SIZE {
+            self.staging.offset = offset + size;
+            let chunk = self.staging.chunks.current();
+            let mapped = chunk.allocation.mapped_ptr().unwrap().as_ptr() as *mut u8;
+            return (chunk.buffer, offset as vk::DeviceSize, unsafe {
+                mapped.add(offset)
+            });
+        }
+
+        // does not fit, so it gets its own buffer. Freed in reset_staging once its frame is done
+        let overflow = self.create_staging_buffer(size);
+        let result = (
+            overflow.buffer,
+            0,
+            overflow.allocation.mapped_ptr().unwrap().as_ptr() as *mut u8,
+        );
+        self.staging.overflow.push((overflow, self.frame));
+        result
+    }
+
+    fn create_staging_buffer(&mut self, size: usize) -> Buffer {
+        self.create_buffer(
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            size,
+            MemoryLocation::CpuToGpu,
+        )
+    }
+
+    // everything staged until end_single_time_staging gets its own buffer instead of arena space
+    // meant to wrap begin_single_time_command_buffer .. end_single_time_command_buffer
+    pub(crate) fn begin_single_time_staging(&mut self) {
+        assert!(
+            self.staging.single_time.is_none(),
+            "Nested single time staging"
+        );
+        self.staging.single_time = Some(vec![]);
+    }
+
+    // call after GPU is done with them (end_single_time_command_buffer waits for it)
+    pub(crate) fn end_single_time_staging(&mut self) {
+        for buffer in self.staging.single_time.take().expect("No single time staging to end") {
+            self.destroy_buffer(buffer);
+        }
+    }
+
+    // called from start_frame after fence wait: chunk of this frame is not used by GPU anymore
+    pub(crate) fn reset_staging(&mut self) {
+        if !self.staging.chunks.is_empty() {
+            self.staging.chunks.move_next();
+        }
+        self.staging.offset = 0;
+
+        let completed_frame = self.completed_frame;
+        let (done, pending) = std::mem::take(&mut self.staging.overflow)
+            .into_iter()
+            .partition(|(_, frame)| *frame <= completed_frame);
+        self.staging.overflow = pending;
+        for (buffer, _) in done {
+            self.destroy_buffer(buffer);
+        }
+    }
+
+    // GPU has to be idle
+    pub(crate) fn destroy_staging(&mut self) {
+        let chunks = std::mem::take(&mut self.staging.chunks);
+        self.destroy_buffer_ring(chunks);
+        for (buffer, _) in std::mem::take(&mut self.staging.overflow) {
+            self.destroy_buffer(buffer);
+        }
+    }
+}
@@ -0,0 +1,567 @@
+// loading of file-based textures into Images
+// KTX2 and DDS are parsed by hand (they are just headers + raw data), PNG goes through png crate
+
+use std::fmt;
+use std::path::Path;
+
+use crate::formats::{format_block, format_srgb_pair};
+use crate::images::{ImageDesc, ImageRegion};
+use crate::{Image, Renderer};
+use ash::vk;
+
+// decoded texture, ready to be copied into Image as is
+// levels[mip] holds all layers (faces are layers too) of that mip, tightly packed one after another
+#[derive(Clone, Debug, Default)]
+pub struct TextureData {
+    pub format: vk::Format,
+    pub extent: vk::Extent3D,
+    pub layers: u32,
+    pub cube: bool,
+    pub levels: Vec<Vec<u8>>,
+}
+
+// what can go wrong with texture file. GPU side problems still panic, like everywhere else
+#[derive(Debug)]
+pub enum TextureError {
+    Io(std::io::Error),
+    Png(png::DecodingError),
+    Malformed(String),   // truncated / corrupt file
+    Unsupported(String), // valid file, but lumal (or device) can not use it
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextureError::Io(e) => write!(f, "failed to read texture: {e}"),
+            TextureError::Png(e) => write!(f, "failed to decode PNG: {e}"),
+            TextureError::Malformed(msg) => write!(f, "malformed texture: {msg}"),
+            TextureError::Unsupported(msg) => write!(f, "unsupported texture: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for TextureError {}
+
+impl From<std::io::Error> for TextureError {
+    fn from(e: std::io::Error) -> Self {
+        TextureError::Io(e)
+    }
+}
+
+impl From<png::DecodingError> for TextureError {
+    fn from(e: png::DecodingError) -> Self {
+        TextureError::Png(e)
+    }
+}
+
+fn malformed<T>(msg: impl Into<String>) -> Result<T, TextureError> {
+    Err(TextureError::Malformed(msg.into()))
+}
+
+fn unsupported<T>(msg: impl Into<String>) -> Result<T, TextureError> {
+    Err(TextureError::Unsupported(msg.into()))
+}
+
+// bytes[offset..offset + length], or error if file is too short
+fn file_range(bytes: &[u8], offset: usize, length: usize) -> Result<&[u8], TextureError> {
+    match offset.checked_add(length) {
+        Some(end) if end <= bytes.len() => Ok(&bytes[offset..end]),
+        _ => malformed(format!(
+            "data at {offset}..+{length} is past end of file ({} bytes)",
+            bytes.len()
+        )),
+    }
+}
+
+// bigger than any device supports, rejects silly headers early. Size math is checked anyway
+const MAX_DIMENSION: u32 = 1 << 16;
+
+fn too_big<T>() -> Result<T, TextureError> {
+    malformed("texture size overflows")
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn mip_size(size: u32, mip: u32) -> u32 {
+    (size >> mip).max(1)
+}
+
+impl TextureData {
+    // size of single layer of given mip
+    pub fn layer_size(&self, mip: u32) -> usize {
+        self.checked_layer_size(mip).expect("Texture size overflows")
+    }
+
+    fn checked_layer_size(&self, mip: u32) -> Option<usize> {
+        let block = format_block(self.format)
+            .unwrap_or_else(|| panic!("Unsupported texture format {:?}", self.format));
+        let width = mip_size(self.extent.width, mip).div_ceil(block.width) as usize;
+        let height = mip_size(self.extent.height, mip).div_ceil(block.height) as usize;
+        let depth = mip_size(self.extent.depth, mip) as usize;
+        width.checked_mul(height)?.checked_mul(depth)?.checked_mul(block.bytes as usize)
+    }
+
+    // size of given mip with all layers, Malformed if it does not fit into usize
+    fn level_size(&self, mip: u32) -> Result<usize, TextureError> {
+        match self
+            .checked_layer_size(mip)
+            .and_then(|size| size.checked_mul(self.layers as usize))
+        {
+            Some(size) => Ok(size),
+            None => too_big(),
+        }
+    }
+
+    fn check_format(format: vk::Format) -> Result<(), TextureError> {
+        match format_block(format) {
+            Some(_) => Ok(()),
+            None => unsupported(format!("format {format:?}")),
+        }
+    }
+
+    // picks decoder by file extension
+    #[cold]
+    #[optimize(size)]
+    pub fn load<P: AsRef<Path>>(path: P, srgb: bool) -> Result<TextureData, TextureError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        match extension.to_ascii_lowercase().as_str() {
+            "png" => Self::from_png(&bytes, srgb),
+            "ktx2" => Self::from_ktx2(&bytes),
+            "dds" => Self::from_dds(&bytes),
+            _ => unsupported(format!("file type of {}", path.display())),
+        }
+    }
+
+    // 8 bit PNGs become RGBA8, 16 bit ones RGBA16
+    #[cold]
+    #[optimize(size)]
+    pub fn from_png(bytes: &[u8], srgb: bool) -> Result<TextureData, TextureError> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info()?;
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels)?;
+        pixels.truncate(info.buffer_size());
+
+        let channel_size = match info.bit_depth {
+            png::BitDepth::Sixteen => 2,
+            _ => 1,
+        };
+        let channels = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::Rgb => 3,
+            png::ColorType::Rgba => 4,
+            png::ColorType::Indexed => return unsupported("PNG palette was not expanded"),
+        };
+
+        // GPUs do not like 3 channel formats, so everything is RGBA
+        let texel_count = (info.width * info.height) as usize;
+        let mut rgba = Vec::with_capacity(texel_count * 4 * channel_size);
+        for texel in pixels.chunks_exact(channels * channel_size) {
+            let channel = |c: usize| &texel[c * channel_size..(c + 1) * channel_size];
+            let one: &[u8] = &[0xff; 2][..channel_size];
+            let (r, g, b, a) = match channels {
+                1 => (channel(0), channel(0), channel(0), one),
+                2 => (channel(0), channel(0), channel(0), channel(1)),
+                3 => (channel(0), channel(1), channel(2), one),
+                _ => (channel(0), channel(1), channel(2), channel(3)),
+            };
+            for c in [r, g, b, a] {
+                // PNG stores 16 bit values big endian
+                rgba.extend(c.iter().rev());
+            }
+        }
+
+        let format = match (channel_size, srgb) {
+            (2, _) => vk::Format::R16G16B16A16_UNORM,
+            (_, true) => vk::Format::R8G8B8A8_SRGB,
+            (_, false) => vk::Format::R8G8B8A8_UNORM,
+        };
+
+        Ok(TextureData {
+            format,
+            extent: vk::Extent3D {
+                width: info.width,
+                height: info.height,
+                depth: 1,
+            },
+            layers: 1,
+            cube: false,
+            levels: vec![rgba],
+        })
+    }
+
+    // supercompressed (zstd, basis) KTX2 files are not supported
+    #[cold]
+    #[optimize(size)]
+    pub fn from_ktx2(bytes: &[u8]) -> Result<TextureData, TextureError> {
+        const IDENTIFIER: [u8; 12] = [
+            0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+        ];
+        if bytes.len() < 80 || bytes[..12] != IDENTIFIER {
+            return malformed("not a KTX2 file");
+        }
+
+        let format = vk::Format::from_raw(read_u32(bytes, 12) as i32);
+        let width = read_u32(bytes, 20);
+        let height = read_u32(bytes, 24).max(1);
+        let depth = read_u32(bytes, 28).max(1);
+        let layer_count = read_u32(bytes, 32).max(1);
+        let face_count = read_u32(bytes, 36);
+        let level_count = read_u32(bytes, 40).max(1);
+        let supercompression = read_u32(bytes, 44);
+
+        if format == vk::Format::UNDEFINED {
+            return unsupported("KTX2 without vkFormat (Basis)");
+        }
+        if supercompression != 0 {
+            return unsupported("supercompressed KTX2");
+        }
+        if face_count != 1 && face_count != 6 {
+            return malformed(format!("KTX2 face count {face_count}, has to be 1 or 6"));
+        }
+        if width == 0
+            || width.max(height).max(depth) > MAX_DIMENSION
+            || layer_count > MAX_DIMENSION
+            || level_count > 32
+        {
+            return malformed(format!(
+                "KTX2 header ({width}x{height}x{depth}, {layer_count} layers, {level_count} levels)"
+            ));
+        }
+        Self::check_format(format)?;
+        let Some(layers) = layer_count.checked_mul(face_count) else {
+            return too_big();
+        };
+
+        let mut texture = TextureData {
+            format,
+            extent: vk::Extent3D {
+                width,
+                height,
+                depth,
+            },
+            layers,
+            cube: face_count == 6,
+            levels: vec![],
+        };
+
+        // level index starts right after 80 byte header, 3 u64's per level
+        let level_index = file_range(bytes, 80, level_count as usize * 24)?;
+        for mip in 0..level_count {
+            let index = mip as usize * 24;
+            let offset = read_u64(level_index, index) as usize;
+            let length = read_u64(level_index, index + 8) as usize;
+            let expected = texture.level_size(mip)?;
+            if length != expected {
+                return malformed(format!(
+                    "KTX2 level {mip} is {length} bytes, expected {expected}"
+                ));
+            }
+            texture.levels.push(file_range(bytes, offset, length)?.to_vec());
+        }
+
+        Ok(texture)
+    }
+
+    // DX10 extended header is supported, legacy header only for DXTn / ATIn and 32 bit RGBA
+    #[cold]
+    #[optimize(size)]
+    pub fn from_dds(bytes: &[u8]) -> Result<TextureData, TextureError> {
+        if bytes.len() < 128 || &bytes[..4] != b"DDS " {
+            return malformed("not a DDS file");
+        }
+
+        let height = read_u32(bytes, 12);
+        let width = read_u32(bytes, 16);
+        let depth = read_u32(bytes, 24).max(1);
+        let level_count = read_u32(bytes, 28).max(1);
+        let pf_flags = read_u32(bytes, 80);
+        let four_cc = &bytes[84..88];
+        let rgb_bit_count = read_u32(bytes, 88);
+        let r_mask = read_u32(bytes, 92);
+        let caps2 = read_u32(bytes, 112);
+
+        const DDPF_FOURCC: u32 = 0x4;
+        const DDSCAPS2_CUBEMAP: u32 = 0x200;
+        const DDS_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+        let mut data_offset = 128;
+        let mut layers = 1;
+        let mut cube = caps2 & DDSCAPS2_CUBEMAP != 0;
+
+        let format = if pf_flags & DDPF_FOURCC != 0 {
+            match four_cc {
+                b"DX10" => {
+                    file_range(bytes, 128, 20)?;
+                    let dxgi_format = read_u32(bytes, 128);
+                    let misc_flag = read_u32(bytes, 136);
+                    layers = read_u32(bytes, 140).max(1);
+                    cube = misc_flag & DDS_RESOURCE_MISC_TEXTURECUBE != 0;
+                    data_offset += 20;
+                    match dxgi_to_vk_format(dxgi_format) {
+                        Some(format) => format,
+                        None => return unsupported(format!("DXGI format {dxgi_format}")),
+                    }
+                }
+                b"DXT1" => vk::Format::BC1_RGBA_UNORM_BLOCK,
+                b"DXT2" | b"DXT3" => vk::Format::BC2_UNORM_BLOCK,
+                b"DXT4" | b"DXT5" => vk::Format::BC3_UNORM_BLOCK,
+                b"ATI1" | b"BC4U" => vk::Format::BC4_UNORM_BLOCK,
+                b"BC4S" => vk::Format::BC4_SNORM_BLOCK,
+                b"ATI2" | b"BC5U" => vk::Format::BC5_UNORM_BLOCK,
+                b"BC5S" => vk::Format::BC5_SNORM_BLOCK,
+                _ => return unsupported(format!("DDS FourCC {four_cc:?}")),
+            }
+        } else {
+            match (rgb_bit_count, r_mask) {
+                (32, 0x000000ff) => vk::Format::R8G8B8A8_UNORM,
+                (32, 0x00ff0000) => vk::Format::B8G8R8A8_UNORM,
+                _ => return unsupported("uncompressed DDS pixel format"),
+            }
+        };
+        if width == 0
+            || height == 0
+            || width.max(height).max(depth) > MAX_DIMENSION
+            || layers > MAX_DIMENSION
+            || level_count > 32
+        {
+            return malformed(format!(
+                "DDS header ({width}x{height}x{depth}, {layers} layers, {level_count} levels)"
+            ));
+        }
+        Self::check_format(format)?;
+        let layers = if cube {
+            layers.checked_mul(6)
+        } else {
+            Some(layers)
+        };
+        let Some(layers) = layers else {
+            return too_big();
+        };
+
+        let mut texture = TextureData {
+            format,
+            extent: vk::Extent3D {
+                width,
+                height,
+                depth,
+            },
+            layers,
+            cube,
+            levels: vec![],
+        };
+
+        // DDS stores all mips of layer 0, then all mips of layer 1, etc. We want it mip-major
+        // whole thing is checked before allocating, so corrupt header can not ask for terabytes
+        let mut layer_sizes = Vec::with_capacity(level_count as usize);
+        let mut total: usize = 0;
+        for mip in 0..level_count {
+            let level_size = texture.level_size(mip)?;
+            let Some(sum) = total.checked_add(level_size) else {
+                return too_big();
+            };
+            total = sum;
+            layer_sizes.push(level_size / texture.layers as usize);
+        }
+        file_range(bytes, data_offset, total)?;
+        texture.levels = (0..level_count)
+            .map(|mip| Vec::with_capacity(layer_sizes[mip as usize] * texture.layers as usize))
+            .collect();
+
+        let mut offset = data_offset;
+        for _ in 0..texture.layers {
+            for (mip, size) in layer_sizes.iter().enumerate() {
+                texture.levels[mip].extend_from_slice(file_range(bytes, offset, *size)?);
+                offset += size;
+            }
+        }
+
+        Ok(texture)
+    }
+}
+
+#[rustfmt::skip]
+fn dxgi_to_vk_format(dxgi_format: u32) -> Option<vk::Format> {
+    use vk::Format as F;
+    let format = match dxgi_format {
+        2 => F::R32G32B32A32_SFLOAT,
+        10 => F::R16G16B16A16_SFLOAT,
+        11 => F::R16G16B16A16_UNORM,
+        16 => F::R32G32_SFLOAT,
+        24 => F::A2B10G10R10_UNORM_PACK32,
+        26 => F::B10G11R11_UFLOAT_PACK32,
+        28 => F::R8G8B8A8_UNORM,
+        29 => F::R8G8B8A8_SRGB,
+        34 => F::R16G16_SFLOAT,
+        41 => F::R32_SFLOAT,
+        49 => F::R8G8_UNORM,
+        54 => F::R16_SFLOAT,
+        56 => F::R16_UNORM,
+        61 => F::R8_UNORM,
+        67 => F::E5B9G9R9_UFLOAT_PACK32,
+        71 => F::BC1_RGBA_UNORM_BLOCK,
+        72 => F::BC1_RGBA_SRGB_BLOCK,
+        74 => F::BC2_UNORM_BLOCK,
+        75 => F::BC2_SRGB_BLOCK,
+        77 => F::BC3_UNORM_BLOCK,
+        78 => F::BC3_SRGB_BLOCK,
+        80 => F::BC4_UNORM_BLOCK,
+        81 => F::BC4_SNORM_BLOCK,
+        83 => F::BC5_UNORM_BLOCK,
+        84 => F::BC5_SNORM_BLOCK,
+        87 => F::B8G8R8A8_UNORM,
+        91 => F::B8G8R8A8_SRGB,
+        95 => F::BC6H_UFLOAT_BLOCK,
+        96 => F::BC6H_SFLOAT_BLOCK,
+        98 => F::BC7_UNORM_BLOCK,
+        99 => F::BC7_SRGB_BLOCK,
+        // ASTC as defined by DXGI (never made it to D3D, but some tools still write them)
+        134 => F::ASTC_4X4_UNORM_BLOCK,
+        135 => F::ASTC_4X4_SRGB_BLOCK,
+        _ => return None,
+    };
+    Some(format)
+}
+
+impl Renderer {
+    // loads texture from PNG / KTX2 / DDS file into sampleable Image (left in ShaderRead)
+    // srgb only matters for PNG, other formats carry color space themselves
+    #[cold]
+    #[optimize(size)]
+    pub fn load_texture<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        srgb: bool,
+        generate_mips: bool,
+        #[cfg(feature = "debug_validation_names")] debug_name: Option<&str>,
+    ) -> Result<Image, TextureError> {
+        let texture = TextureData::load(path, srgb)?;
+        self.create_texture(
+            &texture,
+            generate_mips,
+            #[cfg(feature = "debug_validation_names")]
+            debug_name,
+        )
+    }
+
+    // uploads all mips and layers of texture. If it only has one mip and generate_mips is set,
+    // rest of the chain is generated on GPU (Unsupported for block-compressed formats and formats
+    // that can neither be blitted nor written as storage image)
+    #[cold]
+    #[optimize(size)]
+    pub fn create_texture(
+        &mut self,
+        texture: &TextureData,
+        generate_mips: bool,
+        #[cfg(feature = "debug_validation_names")] debug_name: Option<&str>,
+    ) -> Result<Image, TextureError> {
+        if texture.levels.is_empty() {
+            return malformed("texture has no levels");
+        }
+        TextureData::check_format(texture.format)?;
+        let will_generate = generate_mips
+            && texture.levels.len() == 1
+            && texture.extent.width.max(texture.extent.height) > 1;
+        // can not be blitted or written as storage image, mips have to come from file
+        if will_generate && format_block(texture.format).unwrap().is_compressed() {
+            return unsupported(format!(
+                "mip generation for block-compressed {:?}",
+                texture.format
+            ));
+        }
+
+        let image_type = if texture.extent.depth > 1 {
+            vk::ImageType::TYPE_3D
+        } else {
+            vk::ImageType::TYPE_2D
+        };
+        let mut usage = vk::ImageUsageFlags::SAMPLED
+            | vk::ImageUsageFlags::TRANSFER_DST
+            | vk::ImageUsageFlags::TRANSFER_SRC;
+        let flags = if texture.cube {
+            vk::ImageCreateFlags::CUBE_COMPATIBLE
+        } else {
+            vk::ImageCreateFlags::empty()
+        };
+
+        // same data can be interpreted in other color space if device does not like this one
+        let mut candidates = vec![texture.format];
+        candidates.extend(format_srgb_pair(texture.format));
+        let find_format = |renderer: &Self, candidates: &[vk::Format], usage| {
+            renderer.find_supported_format_with_flags(
+                candidates,
+                image_type,
+                vk::ImageTiling::OPTIMAL,
+                usage,
+                flags,
+            )
+        };
+        let Some(mut format) = find_format(self, &candidates, usage) else {
+            return unsupported(format!("format {:?} on this device", texture.format));
+        };
+
+        // same choice cmd_generate_mipmaps makes, compute fallback needs STORAGE usage
+        if will_generate && !self.can_blit_mipmaps(format, vk::Filter::LINEAR) {
+            let storage_usage = usage | vk::ImageUsageFlags::STORAGE;
+            let storage_format = candidates
+                .iter()
+                .copied()
+                .filter(|&candidate| self.can_compute_mipmaps(candidate))
+                .find(|&candidate| find_format(self, &[candidate], storage_usage).is_some());
+            let Some(storage_format) = storage_format else {
+                return unsupported(format!(
+                    "mip generation for {:?} on this device (neither blit nor storage)",
+                    texture.format
+                ));
+            };
+            format = storage_format;
+            usage = storage_usage;
+        }
+
+        let full_chain = 32 - texture.extent.width.max(texture.extent.height).leading_zeros();
+        let mip_levels = if will_generate {
+            full_chain
+        } else {
+            texture.levels.len() as u32
+        };
+
+        let mut image = self.create_layered_image(
+            &ImageDesc {
+                image_type,
+                format,
+                usage,
+                extent: texture.extent,
+                mip_levels,
+                layers: texture.layers,
+                flags,
+                ..Default::default()
+            },
+            #[cfg(feature = "debug_validation_names")]
+            debug_name,
+        );
+
+        let cmdbuf = self.begin_single_time_command_buffer();
+        self.begin_single_time_staging();
+        for (mip, level) in texture.levels.iter().enumerate() {
+            let region = ImageRegion::whole_mip(&image, mip as u32);
+            self.cmd_upload_image(&cmdbuf, &mut image, level, 0, &region);
+        }
+        if will_generate {
+            self.cmd_generate_mipmaps(&cmdbuf, &mut image, vk::Filter::LINEAR);
+        }
+        self.end_single_time_command_buffer(cmdbuf);
+        self.end_single_time_staging();
+
+        Ok(image)
+    }
+}
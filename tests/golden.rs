@@ -0,0 +1,164 @@
+// headless golden image test: draws a triangle through a RasterPipe and compares with tests/golden
+// needs a Vulkan device (lavapipe works), so it is #[ignore]d. Opt in with
+//   cargo test --test golden -- --ignored
+// and it fails (does not skip) when there is no device
+// set LUMAL_UPDATE_GOLDEN=1 to rewrite tests/golden/*.png
+// shaders are in tests/shaders, .spv is checked in (glslc triangle.vert -o triangle.vert.spv)
+
+use lumal::barriers::ImageUsage;
+use lumal::descriptors::{
+    AttachmentDescription, BlendAttachment, DepthTesting, LoadStoreOp, ShaderStage,
+    SubpassDescription,
+};
+use lumal::golden::check_golden;
+use lumal::{vk, LumalSettings, RasterPipe, Renderer};
+
+// vulkan wants spirv 4-byte aligned, include_bytes! is not
+#[repr(C, align(4))]
+struct Spirv<T: ?Sized>(T);
+
+static TRIANGLE_VERT: &Spirv<[u8]> = &Spirv(*include_bytes!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/shaders/triangle.vert.spv"
+)));
+static TRIANGLE_FRAG: &Spirv<[u8]> = &Spirv(*include_bytes!(concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/shaders/triangle.frag.spv"
+)));
+
+const TRIANGLE_GOLDEN: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/triangle.png");
+const SIZE: u32 = 64;
+
+// what triangle.vert + triangle.frag should produce over black:
+// pixel is covered when its center is left-above the diagonal, see triangle.vert
+fn expected_triangle() -> Vec<u8> {
+    let mut pixels = Vec::with_capacity((SIZE * SIZE * 4) as usize);
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            if x + y < SIZE {
+                pixels.extend_from_slice(&[255, 0, 128, 255]);
+            } else {
+                pixels.extend_from_slice(&[0, 0, 0, 255]);
+            }
+        }
+    }
+    pixels
+}
+
+// cpu only, makes sure checked in reference decodes and has expected contents
+#[test]
+fn triangle_golden_is_valid() {
+    let report = check_golden(&expected_triangle(), SIZE, SIZE, TRIANGLE_GOLDEN, 0);
+    assert!(report.updated || report.max_error == 0);
+}
+
+#[test]
+#[ignore = "needs a Vulkan device (e.g. lavapipe), run with --ignored"]
+fn triangle_matches_golden() {
+    let mut renderer = Renderer::create_headless(&LumalSettings::create_default());
+
+    let mut targets = renderer.create_image_ring(
+        1,
+        vk::ImageType::TYPE_2D,
+        vk::Format::R8G8B8A8_UNORM,
+        vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+        vk::ImageAspectFlags::COLOR,
+        vk::Extent3D {
+            width: SIZE,
+            height: SIZE,
+            depth: 1,
+        },
+        1,
+        vk::SampleCountFlags::TYPE_1,
+        #[cfg(feature = "debug_validation_names")]
+        Some("golden target"),
+    );
+
+    // no descriptors, pipe still needs its (empty) set layout
+    let mut pipe = RasterPipe::default();
+    renderer.anounce_descriptor_setup(
+        &mut pipe.set_layout,
+        &mut pipe.sets,
+        &[],
+        vk::ShaderStageFlags::ALL_GRAPHICS,
+        vk::DescriptorSetLayoutCreateFlags::empty(),
+        #[cfg(feature = "debug_validation_names")]
+        Some("triangle"),
+    );
+    renderer.flush_descriptor_setup();
+    renderer.acutally_setup_named_descriptor(
+        &mut pipe.set_layout,
+        &mut pipe.sets,
+        &[],
+        vk::ShaderStageFlags::ALL_GRAPHICS,
+        vk::DescriptorSetLayoutCreateFlags::empty(),
+        Some("triangle"),
+    );
+
+    let mut rpass = renderer.create_renderpass(
+        &[AttachmentDescription {
+            images: targets.as_ptr(),
+            load: LoadStoreOp::Clear,
+            store: LoadStoreOp::Store,
+            sload: LoadStoreOp::DontCare,
+            sstore: LoadStoreOp::DontCare,
+            clear: vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [0.0, 0.0, 0.0, 1.0],
+                },
+            },
+            final_layout: vk::ImageLayout::GENERAL,
+        }],
+        &mut [SubpassDescription {
+            pipes: &mut [&mut pipe],
+            a_input: &[],
+            a_color: &[targets.as_ptr()],
+            a_depth: None,
+        }],
+    );
+
+    renderer.create_raster_pipe(
+        &mut pipe,
+        &[],
+        &[
+            ShaderStage {
+                stage: vk::ShaderStageFlags::VERTEX,
+                spirv_code: &TRIANGLE_VERT.0,
+            },
+            ShaderStage {
+                stage: vk::ShaderStageFlags::FRAGMENT,
+                spirv_code: &TRIANGLE_FRAG.0,
+            },
+        ],
+        &[],
+        0,
+        vk::VertexInputRate::VERTEX,
+        vk::PrimitiveTopology::TRIANGLE_LIST,
+        rpass.extent,
+        &[BlendAttachment::NoBlend],
+        &[],
+        DepthTesting::DT_None,
+        vk::CompareOp::ALWAYS,
+        vk::CullModeFlags::NONE,
+        vk::StencilOpState::default(),
+        Some("triangle"),
+    );
+
+    renderer.render_offscreen(|renderer, cmdbuf| {
+        renderer.cmd_begin_renderpass(cmdbuf, &rpass, vk::SubpassContents::INLINE);
+        renderer.bind_raster_pipe(cmdbuf, &pipe);
+        unsafe { renderer.device.cmd_draw(*cmdbuf, 3, 1, 0, 0) };
+        renderer.cmd_end_renderpass(cmdbuf, &mut rpass);
+    });
+    // render pass left it in final_layout, tell tracking about it
+    targets[0].assume_usage(ImageUsage::General);
+
+    // 0.5 may round either way. Panics (and writes .actual / .diff pngs) on mismatch
+    let report = renderer.assert_golden(&mut targets[0], TRIANGLE_GOLDEN, 1);
+    assert!(report.updated || report.max_error <= 1);
+
+    renderer.destroy_raster_pipe(pipe);
+    renderer.destroy_renderpass(rpass);
+    renderer.destroy_image_ring(targets);
+    unsafe { renderer.destroy() };
+}
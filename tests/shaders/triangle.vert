@@ -0,0 +1,14 @@
+#version 450
+
+// right triangle over top-left half of the target, no vertex buffer
+// 1.015625 = 1 + 1/64 puts the diagonal half a pixel past pixel centers,
+// so no pixel center lies on an edge and rasterization rules do not matter
+const vec2 positions[3] = vec2[](
+    vec2(-1.0, -1.0),
+    vec2(1.015625, -1.0),
+    vec2(-1.0, 1.015625)
+);
+
+void main() {
+    gl_Position = vec4(positions[gl_VertexIndex], 0.0, 1.0);
+}